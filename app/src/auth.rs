@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::{Rng, distr::Alphanumeric};
+
+/// How long a session can go unused before it expires
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// How long a session lasts at most, after which the webview has to establish a new one with its
+/// window's token
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// The credentials that the server accepts: a token for each open window (which is given to its
+/// webview), and the sessions established with those tokens. Session IDs are separate random
+/// values, so a leaked session cookie can't be used to establish new sessions, and expires on its
+/// own.
+#[derive(Clone, Default)]
+pub struct Auth {
+    state: Arc<Mutex<AuthState>>,
+}
+
+#[derive(Default)]
+struct AuthState {
+    /// The tokens of the open windows
+    windows: Vec<String>,
+    sessions: HashMap<String, Session>,
+}
+
+struct Session {
+    /// The token of the window that established the session
+    window: String,
+    established: Instant,
+    last_used: Instant,
+}
+
+impl Session {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.established) > SESSION_LIFETIME
+            || now.duration_since(self.last_used) > SESSION_IDLE_TIMEOUT
+    }
+}
+
+/// A window's token, which is valid until it is dropped (when the window closes)
+pub struct WindowToken {
+    auth: Auth,
+    token: String,
+}
+
+impl WindowToken {
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+}
+
+impl Drop for WindowToken {
+    fn drop(&mut self) {
+        self.auth.close_window(&self.token);
+    }
+}
+
+impl Auth {
+    /// Issue a token for a new window
+    pub fn open_window(&self) -> WindowToken {
+        let token = random_id();
+        self.state.lock().unwrap().windows.push(token.clone());
+        WindowToken {
+            auth: self.clone(),
+            token,
+        }
+    }
+
+    /// Invalidate a window's token, and every session that was established with it
+    fn close_window(&self, token: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.windows.retain(|t| t != token);
+        state.sessions.retain(|_, session| session.window != token);
+    }
+
+    /// Whether `token` belongs to an open window
+    pub fn is_window_token(&self, token: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .windows
+            .iter()
+            .any(|t| t == token)
+    }
+
+    /// Establish a new session for the window that `token` belongs to, and return its ID
+    pub fn establish_session(&self, token: &str) -> Option<String> {
+        self.establish_session_at(token, Instant::now())
+    }
+
+    fn establish_session_at(&self, token: &str, now: Instant) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        if !state.windows.iter().any(|t| t == token) {
+            return None;
+        }
+        state.sessions.retain(|_, session| !session.is_expired(now));
+        let id = random_id();
+        state.sessions.insert(
+            id.clone(),
+            Session {
+                window: token.to_string(),
+                established: now,
+                last_used: now,
+            },
+        );
+        Some(id)
+    }
+
+    /// Whether `id` is a session that hasn't expired (which keeps it from expiring while it is in
+    /// use)
+    pub fn use_session(&self, id: &str) -> bool {
+        self.use_session_at(id, Instant::now())
+    }
+
+    fn use_session_at(&self, id: &str, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.sessions.get_mut(id) {
+            Some(session) if !session.is_expired(now) => {
+                session.last_used = now;
+                true
+            }
+            Some(_) => {
+                state.sessions.remove(id);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+fn random_id() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn window_tokens_are_valid_until_dropped() {
+        let auth = Auth::default();
+        let window = auth.open_window();
+        let token = window.as_str().to_string();
+        assert!(auth.is_window_token(&token));
+        assert!(!auth.is_window_token("unknown"));
+        drop(window);
+        assert!(!auth.is_window_token(&token));
+        assert!(auth.establish_session(&token).is_none());
+    }
+
+    #[test]
+    fn sessions_need_a_window_token() {
+        let auth = Auth::default();
+        let _window = auth.open_window();
+        assert!(auth.establish_session("unknown").is_none());
+        assert!(auth.establish_session("").is_none());
+    }
+
+    #[test]
+    fn sessions_end_with_their_window() {
+        let auth = Auth::default();
+        let window = auth.open_window();
+        let other = auth.open_window();
+        let session = auth.establish_session(window.as_str()).unwrap();
+        let other_session = auth.establish_session(other.as_str()).unwrap();
+        assert_ne!(session, other_session);
+        assert!(auth.use_session(&session));
+        drop(window);
+        assert!(!auth.use_session(&session));
+        assert!(auth.use_session(&other_session));
+    }
+
+    #[test]
+    fn unknown_sessions_are_rejected() {
+        let auth = Auth::default();
+        let window = auth.open_window();
+        assert!(!auth.use_session("unknown"));
+        // A window token isn't a session
+        assert!(!auth.use_session(window.as_str()));
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let auth = Auth::default();
+        let window = auth.open_window();
+        let start = Instant::now();
+        let session = auth.establish_session_at(window.as_str(), start).unwrap();
+        let almost_idle = start + SESSION_IDLE_TIMEOUT - SECOND;
+        assert!(auth.use_session_at(&session, almost_idle));
+        // Using the session keeps it from expiring
+        assert!(auth.use_session_at(&session, almost_idle + SESSION_IDLE_TIMEOUT - SECOND));
+
+        let idle = almost_idle + 2 * SESSION_IDLE_TIMEOUT;
+        assert!(!auth.use_session_at(&session, idle));
+        // Expired sessions are removed, so they stay expired
+        assert!(!auth.use_session_at(&session, almost_idle));
+    }
+
+    #[test]
+    fn sessions_expire_after_their_lifetime_even_if_used() {
+        let auth = Auth::default();
+        let window = auth.open_window();
+        let start = Instant::now();
+        let session = auth.establish_session_at(window.as_str(), start).unwrap();
+        let mut now = start;
+        while now + SESSION_IDLE_TIMEOUT / 2 < start + SESSION_LIFETIME {
+            now += SESSION_IDLE_TIMEOUT / 2;
+            assert!(auth.use_session_at(&session, now));
+        }
+        assert!(!auth.use_session_at(&session, start + SESSION_LIFETIME + SECOND));
+        // The window can establish a new session
+        let later = start + SESSION_LIFETIME + SECOND;
+        let new_session = auth.establish_session_at(window.as_str(), later).unwrap();
+        assert!(auth.use_session_at(&new_session, later));
+    }
+
+    #[test]
+    fn expired_sessions_are_pruned_when_establishing_new_ones() {
+        let auth = Auth::default();
+        let window = auth.open_window();
+        let start = Instant::now();
+        let session = auth.establish_session_at(window.as_str(), start).unwrap();
+        let later = start + SESSION_IDLE_TIMEOUT + SECOND;
+        auth.establish_session_at(window.as_str(), later).unwrap();
+        let state = auth.state.lock().unwrap();
+        assert_eq!(state.sessions.len(), 1);
+        assert!(!state.sessions.contains_key(&session));
+    }
+}
@@ -6,7 +6,7 @@ use std::process::Stdio;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::expansion::shell_expand;
-use crate::io::IOFormat;
+use crate::io::{Encoding, IOFormat};
 
 #[cfg(windows)]
 const LINE_ENDING: &str = "\r\n";
@@ -19,6 +19,14 @@ pub struct Command {
     pub command: Vec<String>,
     #[serde(default)]
     pub output_format: IOFormat,
+    /// Text encoding of the command's output
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// A jq filter to apply to the command's parsed output
+    pub transform: Option<String>,
+    /// Use the transform's only output instead of an array of its outputs
+    #[serde(default)]
+    pub unwrap_transform: bool,
 }
 
 impl Command {
@@ -1,21 +1,48 @@
-use anyhow::{Context, Result};
-use clap::{ArgAction, Args, Parser, ValueHint};
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result, bail};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueHint};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::iter;
 use std::path::PathBuf;
 use tao::dpi::{PhysicalPosition, PhysicalSize};
 
 use crate::command::Command;
 use crate::expansion::expand_path;
-use crate::io::IOFormat;
+use crate::io::{BuiltinSource, Encoding, IOFormat};
+use crate::schema::SchemaSource;
+use crate::security::{Network, Permissions};
 
 use polymenu_derive::UpdateFromOther;
 
+/// The `port` value that lets the OS pick a free port for the server
+pub const AUTO_PORT: &str = "auto";
+
 pub trait UpdateFromOther {
     fn update_from_other(&mut self, other: Self);
 }
 
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+#[clap(disable_help_flag = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub mode: Option<Mode>,
+
+    #[command(flatten)]
+    pub config: Config,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Mode {
+    /// Keep a hidden window and server running in the background, so that apps can be shown
+    /// instantly with `polymenu show`
+    Daemon,
+    /// Show an app in the running daemon and print its output (takes the same options as running
+    /// the app directly)
+    Show(Box<Config>),
+}
+
 #[derive(UpdateFromOther, Parser, Serialize, Deserialize, Clone, Default, Debug)]
 #[command(author, version, about, long_about = None)]
 #[clap(disable_help_flag = true)]
@@ -33,6 +60,19 @@ pub struct Config {
     #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
     pub file: Option<PathBuf>,
 
+    /// Read items from the output of a command instead of STDIN
+    #[clap(skip)]
+    pub input_command: Option<Vec<String>>,
+
+    /// Read items from a built-in source instead of STDIN
+    #[arg(
+        long,
+        value_enum,
+        value_name = "SOURCE",
+        conflicts_with_all = ["file", "__input_command_cli"]
+    )]
+    pub builtin: Option<BuiltinSource>,
+
     /// Format of STDIN or file input
     #[arg(long, value_enum, value_name = "FORMAT")]
     pub format: Option<IOFormat>,
@@ -41,6 +81,51 @@ pub struct Config {
     #[arg(long, value_name = "COLUMN NAMES",  num_args = 1..)]
     pub headers: Option<Vec<String>>,
 
+    /// Skip invalid lines instead of failing, and report them alongside the data (only used when
+    /// format is json-lines)
+    #[arg(long)]
+    #[serde(default)]
+    pub skip_invalid: bool,
+
+    /// Read every table in the input, keyed by the heading that precedes it (only used when
+    /// format is markdown-table or org-table)
+    #[arg(long)]
+    #[serde(default)]
+    pub all_tables: bool,
+
+    /// Text encoding of STDIN or file input (default is utf8)
+    #[arg(long, value_enum, value_name = "ENCODING")]
+    pub encoding: Option<Encoding>,
+
+    /// JSON Schema that each input record is validated against (inline, or a path to a JSON file)
+    #[clap(skip)]
+    pub schema: Option<SchemaSource>,
+
+    /// A jq filter to apply to the parsed input (e.g. `.[] | {key: .name}`). Its outputs are
+    /// collected into an array.
+    #[arg(long, value_name = "FILTER")]
+    pub transform: Option<String>,
+
+    /// Use the transform's only output instead of an array of its outputs (it is an error if it
+    /// doesn't produce exactly one)
+    #[arg(long, requires = "transform")]
+    #[serde(default)]
+    pub unwrap_transform: bool,
+
+    /// Additional named inputs that will be served to the webview via `/api/input/{key}`
+    #[clap(skip)]
+    #[serde(default)]
+    pub inputs: HashMap<String, InputConfig>,
+
+    /// Watch input files and mounted directories, and push changes to the webview
+    #[arg(long)]
+    #[serde(default)]
+    pub watch: bool,
+
+    /// Icon theme used by `/api/icon/{name}` (default is the GTK or KDE theme)
+    #[arg(long, value_name = "THEME")]
+    pub icon_theme: Option<String>,
+
     /// Options to be passed to the app at runtime
     #[clap(skip)]
     #[serde(default)]
@@ -48,29 +133,72 @@ pub struct Config {
 
     /// A set of directories that will be served to the webview via `/files/{key}`
     #[clap(skip)]
-    #[serde(default)]
-    pub mounts: HashMap<String, PathBuf>,
+    #[serde(default, deserialize_with = "deserialize_mounts")]
+    pub mounts: HashMap<String, MountConfig>,
 
     /// CLI commands that can be run from the webview using their associated key
     #[clap(skip)]
     #[serde(default)]
     pub commands: HashMap<String, Command>,
 
+    /// Which hosts the webview can reach besides the app itself: `deny` (default), `allow`, or a
+    /// comma separated list of domains
+    #[arg(long, value_name = "NETWORK")]
+    pub network: Option<Network>,
+
+    /// Headers added to every response, replacing the default security headers with the same
+    /// name (e.g. `Content-Security-Policy`). An empty value removes a default header.
+    #[clap(skip)]
+    #[serde(default)]
+    pub security_headers: HashMap<String, String>,
+
+    /// The endpoints, commands and mounts that the app can use (everything if not set)
+    #[clap(skip)]
+    #[serde(default)]
+    pub permissions: Option<Permissions>,
+
     /// Configuration options related to the webview window
     #[command(flatten)]
     #[serde(default)]
     pub window: WindowOptions,
 
-    /// The port that the server should bind to
+    /// The port that the server should bind to (`auto` picks a free port)
     #[arg(short, long, value_name = "PORT", default_value_t = default_port())]
     #[serde(default = "default_port")]
     pub port: String,
 
+    /// Serve the app over a private Unix socket instead of a TCP port (ignores `port`)
+    #[arg(long, conflicts_with = "develop")]
+    #[serde(default)]
+    pub socket: bool,
+
+    /// Serve the app to the webview in-process through the `polymenu://` protocol, without
+    /// listening on a port or socket (ignores `port`)
+    #[arg(long, conflicts_with_all = ["develop", "socket"])]
+    #[serde(default)]
+    pub in_process: bool,
+
+    /// Hide the window instead of exiting when the app is closed, and show it again when the app
+    /// is run again (or receives `SIGUSR1`)
+    #[arg(long)]
+    #[serde(default)]
+    pub resident: bool,
+
     /// Launch in development mode
     #[arg(long)]
     #[serde(skip)]
     pub develop: bool,
 
+    /// Enable the webview's developer tools (always enabled in development mode)
+    #[arg(long)]
+    #[serde(default)]
+    pub devtools: bool,
+
+    /// Whether this is the daemon's config (set by `polymenu daemon`)
+    #[clap(skip)]
+    #[serde(skip)]
+    pub daemon: bool,
+
     /// Compile / build your app
     #[arg(long)]
     #[serde(skip)]
@@ -105,6 +233,15 @@ pub struct Config {
     #[serde(skip)]
     __mounts_cli: Vec<String>,
 
+    /// Read items from the output of a command instead of STDIN
+    #[arg(
+        long = "input-command",
+        value_name = "COMMAND",
+        conflicts_with = "file"
+    )]
+    #[serde(skip)]
+    __input_command_cli: Option<String>,
+
     #[clap(long, action = clap::ArgAction::HelpLong)]
     #[serde(skip)]
     help: Option<bool>,
@@ -112,6 +249,13 @@ pub struct Config {
 
 impl Config {
     pub fn gui_target_url(&self) -> String {
+        #[cfg(unix)]
+        let socket = self.socket;
+        #[cfg(not(unix))]
+        let socket = false;
+        if (socket || self.in_process) && !self.develop {
+            return crate::protocol::url();
+        }
         format!(
             "http://localhost:{}",
             if self.develop {
@@ -122,8 +266,48 @@ impl Config {
         )
     }
 
+    /// The app's default input (configured by the top-level input properties, e.g. `file`)
+    pub fn default_input(&self) -> InputConfig {
+        InputConfig {
+            file: self.file.clone(),
+            command: self.input_command.clone(),
+            builtin: self.builtin,
+            format: self.format,
+            headers: self.headers.clone(),
+            skip_invalid: self.skip_invalid,
+            all_tables: self.all_tables,
+            encoding: self.encoding.unwrap_or_default(),
+            schema: self.schema.clone(),
+            transform: self.transform.clone(),
+            unwrap_transform: self.unwrap_transform,
+        }
+    }
+
+    /// The app's default input (with no name) followed by its named inputs
+    pub fn all_inputs(&self) -> impl Iterator<Item = (Option<&String>, InputConfig)> {
+        let named = self
+            .inputs
+            .iter()
+            .map(|(name, input)| (Some(name), input.clone()));
+        iter::once((None, self.default_input())).chain(named)
+    }
+
     pub fn server_url(&self) -> String {
-        format!("127.0.0.1:{}", &self.port)
+        let port = if self.port == AUTO_PORT {
+            "0"
+        } else {
+            &self.port
+        };
+        format!("127.0.0.1:{port}")
+    }
+
+    /// The path of the Unix socket that the server listens on when `socket` is set (unique to this
+    /// instance of polymenu)
+    pub fn socket_path(&self) -> PathBuf {
+        dirs::runtime_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("polymenu")
+            .join(format!("{}.sock", std::process::id()))
     }
 
     pub fn from_file(path: &PathBuf) -> Result<Self> {
@@ -150,8 +334,31 @@ impl Config {
         path
     }
 
-    /// Apply CLI overrides for `options` and `mount`
-    pub fn apply_cli_overrides(mut self) -> Result<Self> {
+    /// Apply the CLI options on top of this config (read from the config file). An input source
+    /// given on the CLI replaces the config file's, rather than conflicting with it.
+    pub fn with_cli_options(mut self, cli_opts: Config) -> Result<Self> {
+        self.default_input()
+            .validate()
+            .context("invalid config file")?;
+        for (name, input) in &self.inputs {
+            input
+                .validate()
+                .with_context(|| format!("invalid input: {name}"))?;
+        }
+        if cli_opts.file.is_some()
+            || cli_opts.builtin.is_some()
+            || cli_opts.__input_command_cli.is_some()
+        {
+            self.file = None;
+            self.input_command = None;
+            self.builtin = None;
+        }
+        self.update_from_other(cli_opts);
+        self.apply_cli_overrides()
+    }
+
+    /// Apply CLI overrides for `options`, `mount`, and `input_command`
+    fn apply_cli_overrides(mut self) -> Result<Self> {
         for s in self.__options_cli.iter() {
             let (k, v) = s
                 .split_once('=')
@@ -166,12 +373,149 @@ impl Config {
                 .with_context(|| format!("expected format for mounts is NAME:PATH, got: {s}"))?;
             let name = k.parse().with_context(|| format!("invalid key: {k}"))?;
             let path = v.parse().with_context(|| format!("invalid value: {v}"))?;
-            self.mounts.insert(name, path);
+            self.mounts.insert(
+                name,
+                MountConfig {
+                    path,
+                    ..Default::default()
+                },
+            );
+        }
+        if let Some(s) = &self.__input_command_cli {
+            let command =
+                shlex::split(s).with_context(|| format!("could not parse input command: {s}"))?;
+            self.input_command = Some(command);
         }
         Ok(self)
     }
 }
 
+/// A source of input data for the app. Reads from STDIN if no `file`, `command`, or `builtin` is
+/// given.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct InputConfig {
+    /// Read items from a file
+    pub file: Option<PathBuf>,
+
+    /// Read items from the output of a command
+    pub command: Option<Vec<String>>,
+
+    /// Read items from a built-in source
+    pub builtin: Option<BuiltinSource>,
+
+    /// Format of the input (inferred from the file extension if not provided)
+    pub format: Option<IOFormat>,
+
+    /// Name of each field (only used when format is headless-csv)
+    pub headers: Option<Vec<String>>,
+
+    /// Skip invalid lines instead of failing, and report them alongside the data (only used when
+    /// format is json-lines)
+    #[serde(default)]
+    pub skip_invalid: bool,
+
+    /// Read every table in the input, keyed by the heading that precedes it (only used when
+    /// format is markdown-table or org-table)
+    #[serde(default)]
+    pub all_tables: bool,
+
+    /// Text encoding of the input
+    #[serde(default)]
+    pub encoding: Encoding,
+
+    /// JSON Schema that each record is validated against (inline, or a path to a JSON file)
+    pub schema: Option<SchemaSource>,
+
+    /// A jq filter to apply to the parsed input
+    pub transform: Option<String>,
+
+    /// Use the transform's only output instead of an array of its outputs
+    #[serde(default)]
+    pub unwrap_transform: bool,
+}
+
+impl InputConfig {
+    /// Check that at most one of `file`, `command`, and `builtin` is set
+    pub fn validate(&self) -> Result<()> {
+        let sources = [
+            self.file.is_some(),
+            self.command.is_some(),
+            self.builtin.is_some(),
+        ];
+        if sources.into_iter().filter(|&set| set).count() > 1 {
+            bail!(
+                "only one of `file`, `input_command` (`command` for named inputs), and `builtin` \
+                 can be set"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A directory served to the webview. Can be written in the config as just a path, or as a table
+/// with a `path` and other options.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MountConfig {
+    pub path: PathBuf,
+
+    /// Allow the webview to create, overwrite, delete and rename files in the mount
+    #[serde(default)]
+    pub writable: bool,
+
+    /// The largest file (in bytes) that can be written to the mount (default is 16 MiB)
+    pub max_file_size: Option<usize>,
+
+    /// Only allow access to files that match one of these globs (e.g. `**/*.{png,jpg}`)
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Deny access to files and directories that match any of these globs (e.g. `private/**`)
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Allow access to files and directories whose name starts with `.`
+    #[serde(default)]
+    pub hidden: bool,
+
+    /// Allow access to files through symlinks within the mount
+    #[serde(default)]
+    pub follow_symlinks: bool,
+}
+
+impl MountConfig {
+    pub const DEFAULT_MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+    pub fn max_file_size(&self) -> usize {
+        self.max_file_size.unwrap_or(Self::DEFAULT_MAX_FILE_SIZE)
+    }
+}
+
+fn deserialize_mounts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, MountConfig>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Mount {
+        Path(PathBuf),
+        Table(MountConfig),
+    }
+
+    let mounts = HashMap::<String, Mount>::deserialize(deserializer)?;
+    Ok(mounts
+        .into_iter()
+        .map(|(name, mount)| match mount {
+            Mount::Path(path) => (
+                name,
+                MountConfig {
+                    path,
+                    ..Default::default()
+                },
+            ),
+            Mount::Table(mount) => (name, mount),
+        })
+        .collect())
+}
+
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Args, Serialize, Deserialize,
 )]
@@ -226,7 +570,7 @@ impl WindowOptions {
 }
 
 fn default_port() -> String {
-    "7777".to_string()
+    AUTO_PORT.to_string()
 }
 
 fn default_dev_server_port() -> String {
@@ -252,3 +596,57 @@ fn default_install_command() -> Option<Vec<String>> {
             .collect(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file: &str, args: &[&str]) -> Result<Config> {
+        let file: Config = toml::from_str(file)?;
+        let cli = Cli::try_parse_from(iter::once("polymenu").chain(args.iter().copied()))?;
+        file.with_cli_options(cli.config)
+    }
+
+    #[test]
+    fn cli_input_sources_replace_the_config_files() {
+        let config = load("file = \"items.json\"", &["--input-command", "ls -a"]).unwrap();
+        assert_eq!(config.file, None);
+        assert_eq!(
+            config.input_command,
+            Some(vec!["ls".to_string(), "-a".to_string()])
+        );
+
+        let config = load("input_command = [\"ls\"]", &["--file", "items.json"]).unwrap();
+        assert_eq!(config.file, Some(PathBuf::from("items.json")));
+        assert_eq!(config.input_command, None);
+
+        let config = load("file = \"items.json\"", &["--builtin", "desktop-entries"]).unwrap();
+        assert_eq!(config.file, None);
+        assert_eq!(config.builtin, Some(BuiltinSource::DesktopEntries));
+
+        // Other options don't affect the input source
+        let config = load("file = \"items.json\"", &["--port", "1234"]).unwrap();
+        assert_eq!(config.file, Some(PathBuf::from("items.json")));
+    }
+
+    #[test]
+    fn inputs_can_only_have_one_source() {
+        assert!(load("file = \"a.json\"\ninput_command = [\"ls\"]", &[]).is_err());
+        assert!(
+            load(
+                "[inputs.a]\nfile = \"a.json\"\nbuiltin = \"desktop_entries\"",
+                &[]
+            )
+            .is_err()
+        );
+        assert!(load("[inputs.a]\ncommand = [\"ls\"]", &[]).is_ok());
+        assert!(load("", &["--file", "a.json", "--input-command", "ls"]).is_err());
+        assert!(
+            load(
+                "",
+                &["--builtin", "desktop-entries", "--input-command", "ls"]
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow, bail};
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use tao::event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedReadHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::runtime::Runtime;
+use tokio::sync::{Mutex, mpsc, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::expansion::expand_path;
+use crate::gui::{AppEvent, run_gui};
+use crate::schema::SchemaSource;
+use crate::server::{self, ClientIo, OnClose, bind_socket};
+
+/// Sent by `polymenu show` to ask the daemon to show an app
+#[derive(Serialize, Deserialize)]
+struct ShowRequest {
+    config: Config,
+    /// The client's STDIN (empty if none of the app's inputs read from STDIN)
+    stdin: Vec<u8>,
+}
+
+/// The values printed by the app, or why it couldn't be shown
+type ShowResponse = Result<Vec<String>, String>;
+
+/// The socket that the daemon listens on
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("polymenu")
+        .join("daemon.sock")
+}
+
+/// Run the daemon: a hidden window that shows apps on behalf of `polymenu show`, until it is
+/// interrupted
+pub fn run(rt: &Runtime) -> Result<()> {
+    let mut config = Config::default();
+    config.daemon = true;
+    config.in_process = true;
+    let path = socket_path();
+    let listener = rt.block_on(bind(&path))?;
+    server::init_tracing();
+
+    let event_loop: EventLoop<AppEvent> = EventLoopBuilder::with_user_event().build();
+    let event_loop_proxy = event_loop.create_proxy();
+    let shutdown_token = CancellationToken::new();
+    let (app_sender, app_receiver) = watch::channel(None);
+    {
+        let shutdown_token = shutdown_token.clone();
+        rt.spawn(async move {
+            if let Err(e) = serve(
+                listener,
+                app_sender,
+                event_loop_proxy.clone(),
+                shutdown_token,
+            )
+            .await
+            {
+                eprintln!("{e:#}");
+            }
+            let _ = event_loop_proxy.send_event(AppEvent::Shutdown);
+        });
+    }
+
+    let gui_result = run_gui(
+        &config,
+        None,
+        event_loop,
+        shutdown_token.clone(),
+        rt.handle(),
+        app_receiver,
+    );
+    shutdown_token.cancel();
+    let _ = std::fs::remove_file(&path);
+    gui_result
+}
+
+async fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).await.is_ok() {
+        bail!("the daemon is already running (listening on {path:?})");
+    }
+    bind_socket(path)
+}
+
+/// Accept clients until the daemon shuts down. Only one app can be shown at a time, so showing an
+/// app closes the one that is currently shown.
+async fn serve(
+    listener: UnixListener,
+    app_sender: watch::Sender<Option<Router>>,
+    event_loop_proxy: EventLoopProxy<AppEvent>,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    let app_sender = Arc::new(app_sender);
+    let turn = Arc::new(Mutex::new(()));
+    let mut current: Option<CancellationToken> = None;
+    loop {
+        let stream = tokio::select! {
+            _ = shutdown_token.cancelled() => break,
+            _ = tokio::signal::ctrl_c() => break,
+            accepted = listener.accept() => accepted.context("could not accept client")?.0,
+        };
+        let session = shutdown_token.child_token();
+        if let Some(previous) = current.replace(session.clone()) {
+            previous.cancel();
+        }
+        let app_sender = app_sender.clone();
+        let event_loop_proxy = event_loop_proxy.clone();
+        let turn = turn.clone();
+        tokio::spawn(async move {
+            // Wait for the previous app to be hidden
+            let _turn = turn.lock().await;
+            handle(stream, session, &app_sender, &event_loop_proxy).await;
+        });
+    }
+    Ok(())
+}
+
+/// Show the app requested by a client, and send it the app's output once the app closes
+async fn handle(
+    stream: UnixStream,
+    session: CancellationToken,
+    app_sender: &watch::Sender<Option<Router>>,
+    event_loop_proxy: &EventLoopProxy<AppEvent>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let response: ShowResponse = show_app(
+        &mut BufReader::new(reader),
+        session,
+        app_sender,
+        event_loop_proxy,
+    )
+    .await
+    .map_err(|e| format!("{e:#}"));
+    let mut response = serde_json::to_string(&response).expect("responses can be serialized");
+    response.push('\n');
+    let _ = writer.write_all(response.as_bytes()).await;
+}
+
+async fn show_app(
+    reader: &mut BufReader<OwnedReadHalf>,
+    session: CancellationToken,
+    app_sender: &watch::Sender<Option<Router>>,
+    event_loop_proxy: &EventLoopProxy<AppEvent>,
+) -> Result<Vec<String>> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .context("could not read request")?;
+    let request: ShowRequest = serde_json::from_str(&line).context("invalid request")?;
+    let window = request.config.window;
+    let network = request.config.network.clone().unwrap_or_default();
+    let (output, mut printed) = mpsc::unbounded_channel();
+    let io = ClientIo {
+        stdin: Some(request.stdin.into()),
+        output: Some(output),
+    };
+    // The app's router needs to stay alive until it closes
+    let _app = {
+        let app =
+            server::build(request.config, io, None, OnClose::Shutdown, session.clone()).await?;
+        app_sender.send_replace(Some(app.router.clone()));
+        app
+    };
+    event_loop_proxy
+        .send_event(AppEvent::Show {
+            session: session.clone(),
+            window,
+            network,
+        })
+        .map_err(|_| anyhow!("the daemon's window was closed"))?;
+
+    tokio::select! {
+        _ = session.cancelled() => {}
+        // The client doesn't send anything else, so this only returns once it disconnects (e.g.
+        // if it was interrupted)
+        _ = reader.read_line(&mut line) => session.cancel(),
+    }
+    // The hidden webview shouldn't be able to use the app's API anymore
+    app_sender.send_replace(None);
+    let _ = event_loop_proxy.send_event(AppEvent::Hide);
+
+    let mut values = Vec::new();
+    while let Ok(value) = printed.try_recv() {
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// Show an app in the running daemon, and print its output once it closes
+pub async fn show(mut config: Config) -> Result<()> {
+    if config.develop || config.compile {
+        bail!("`show` can't be used with `--develop` or `--compile`");
+    }
+    resolve_paths(&mut config)?;
+    let reads_stdin = config.all_inputs().any(|(_, input)| {
+        input.file.is_none() && input.command.is_none() && input.builtin.is_none()
+    });
+    let mut stdin = Vec::new();
+    if reads_stdin && !std::io::stdin().is_terminal() {
+        std::io::stdin()
+            .read_to_end(&mut stdin)
+            .context("failed to read STDIN")?;
+    }
+
+    let path = socket_path();
+    let stream = UnixStream::connect(&path).await.with_context(|| {
+        format!("could not connect to the daemon at {path:?} (is `polymenu daemon` running?)")
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut request = serde_json::to_string(&ShowRequest { config, stdin })?;
+    request.push('\n');
+    writer
+        .write_all(request.as_bytes())
+        .await
+        .context("could not send request to the daemon")?;
+
+    let mut response = String::new();
+    BufReader::new(reader)
+        .read_line(&mut response)
+        .await
+        .context("could not read response from the daemon")?;
+    if response.is_empty() {
+        bail!("the daemon closed the connection");
+    }
+    let response: ShowResponse =
+        serde_json::from_str(&response).context("invalid response from the daemon")?;
+    for value in response.map_err(|e| anyhow!(e))? {
+        println!("{value}");
+    }
+    Ok(())
+}
+
+/// Make the app's paths absolute (and expand them), since the daemon has a different working
+/// directory and environment
+fn resolve_paths(config: &mut Config) -> Result<()> {
+    let paths = config
+        .src
+        .iter_mut()
+        .chain(config.file.iter_mut())
+        .chain(schema_path(&mut config.schema))
+        .chain(
+            config
+                .inputs
+                .values_mut()
+                .flat_map(|i| i.file.iter_mut().chain(schema_path(&mut i.schema))),
+        )
+        .chain(config.mounts.values_mut().map(|m| &mut m.path));
+    for path in paths {
+        *path = std::path::absolute(expand_path(path)?)
+            .with_context(|| format!("could not resolve path: {path:?}"))?;
+    }
+    Ok(())
+}
+
+fn schema_path(schema: &mut Option<SchemaSource>) -> Option<&mut PathBuf> {
+    match schema {
+        Some(SchemaSource::Path(path)) => Some(path),
+        _ => None,
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+
+use crate::xdg::{data_dirs, parse_key_file};
+
+const DESKTOP_ENTRY_GROUP: &str = "Desktop Entry";
+
+/// Terminal emulators to try (in order) when launching an application with `Terminal=true` and
+/// `$TERMINAL` is not set
+const TERMINALS: &[&str] = &[
+    "xdg-terminal-exec",
+    "x-terminal-emulator",
+    "kitty",
+    "alacritty",
+    "foot",
+    "wezterm",
+    "ghostty",
+    "gnome-terminal",
+    "konsole",
+    "xterm",
+];
+
+/// An application described by a freedesktop `.desktop` entry
+#[derive(Debug, Clone, Serialize)]
+pub struct DesktopEntry {
+    /// The desktop file ID (e.g. `org.mozilla.firefox.desktop`)
+    pub id: String,
+    pub name: String,
+    pub generic_name: Option<String>,
+    pub comment: Option<String>,
+    pub icon: Option<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// The command that launches the application (with field codes expanded, and wrapped in a
+    /// terminal emulator if the application runs in a terminal)
+    pub exec: Vec<String>,
+    pub terminal: bool,
+    /// The working directory to launch the application in
+    pub working_directory: Option<PathBuf>,
+    /// The path to the `.desktop` file
+    pub path: PathBuf,
+}
+
+impl DesktopEntry {
+    /// Launch the application, detached from polymenu (this must be called from within the
+    /// tokio runtime, which reaps the application once it exits)
+    pub fn launch(&self) -> Result<()> {
+        let (program, args) = self
+            .exec
+            .split_first()
+            .ok_or_else(|| anyhow!("desktop entry `{}` has an empty `Exec`", self.id))?;
+        let mut command = std::process::Command::new(program);
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(dir) = &self.working_directory {
+            command.current_dir(dir);
+        }
+        // Start the application in its own process group, so that it isn't killed when the
+        // menu exits
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        let mut child = tokio::process::Command::from(command)
+            .spawn()
+            .with_context(|| format!("failed to launch `{}`", self.id))?;
+        // Wait for the application in the background, so that it doesn't become a zombie once it
+        // exits
+        tokio::spawn(async move {
+            let _ = child.wait().await;
+        });
+        Ok(())
+    }
+}
+
+/// All applications that should be shown to the user, from the `.desktop` files in the XDG
+/// application directories
+pub fn desktop_entries() -> Vec<DesktopEntry> {
+    let locale = Locale::from_env();
+    let current_desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .map(|s| s.split(':').map(str::to_string).collect())
+        .unwrap_or_default();
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for dir in application_dirs() {
+        for (id, path) in desktop_files(&dir) {
+            // Files in earlier directories take precedence over files with the same ID in later
+            // directories (even if they are hidden)
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            let Ok(src) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let fields = parse_desktop_entry(&src);
+            if let Some(entry) = to_desktop_entry(id, path, &fields, &locale, &current_desktops) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries.sort_by_key(|e| e.name.to_lowercase());
+    entries
+}
+
+/// The `applications` directory of each XDG data directory
+fn application_dirs() -> Vec<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// The `.desktop` files under `dir` and their desktop file IDs (the path relative to `dir`, with
+/// `/` replaced by `-`)
+fn desktop_files(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut files = Vec::new();
+    // Symlinked directories are followed, so make sure each directory is only read once (in case
+    // the symlinks form a cycle)
+    let mut visited = HashSet::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        if !std::fs::canonicalize(&current).is_ok_and(|canonical| visited.insert(canonical)) {
+            continue;
+        }
+        let Ok(read_dir) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for path in read_dir.flatten().map(|e| e.path()) {
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e == "desktop")
+                && let Ok(relative) = path.strip_prefix(dir)
+            {
+                let id = relative.to_string_lossy().replace('/', "-");
+                files.push((id, path));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Read the key-value pairs of the `[Desktop Entry]` group
+fn parse_desktop_entry(src: &str) -> HashMap<&str, &str> {
+    parse_key_file(src)
+        .remove(DESKTOP_ENTRY_GROUP)
+        .unwrap_or_default()
+}
+
+fn to_desktop_entry(
+    id: String,
+    path: PathBuf,
+    fields: &HashMap<&str, &str>,
+    locale: &Locale,
+    current_desktops: &[String],
+) -> Option<DesktopEntry> {
+    let get = |key: &str| fields.get(key).map(|v| unescape(v));
+    let get_raw_localized = |key: &str| {
+        locale
+            .keys(key)
+            .iter()
+            .find_map(|k| fields.get(k.as_str()).copied())
+    };
+    let get_localized = |key: &str| get_raw_localized(key).map(unescape);
+    let get_bool = |key: &str| fields.get(key).is_some_and(|v| *v == "true");
+    // Lists are split before their items are unescaped, so that an escaped backslash followed by
+    // a separator (`\\;`) isn't mistaken for an escaped separator
+    let get_list = |value: Option<&str>| -> Vec<String> {
+        value
+            .map(|v| split_list(v).iter().map(|item| unescape(item)).collect())
+            .unwrap_or_default()
+    };
+
+    if fields.get("Type") != Some(&"Application") || get_bool("Hidden") || get_bool("NoDisplay") {
+        return None;
+    }
+    let shown_in = |key: &str| {
+        get_list(fields.get(key).copied())
+            .iter()
+            .any(|desktop| current_desktops.contains(desktop))
+    };
+    if (fields.contains_key("OnlyShowIn") && !shown_in("OnlyShowIn")) || shown_in("NotShowIn") {
+        return None;
+    }
+    if let Some(try_exec) = get("TryExec")
+        && find_executable(&try_exec).is_none()
+    {
+        return None;
+    }
+
+    let name = get_localized("Name")?;
+    let icon = get_localized("Icon");
+    let terminal = get_bool("Terminal");
+    let mut exec = expand_field_codes(&split_exec(&get("Exec")?)?, &name, icon.as_deref(), &path);
+    if terminal && let Some(wrapped) = wrap_in_terminal(exec.clone()) {
+        exec = wrapped;
+    }
+    Some(DesktopEntry {
+        id,
+        generic_name: get_localized("GenericName"),
+        comment: get_localized("Comment"),
+        keywords: get_list(get_raw_localized("Keywords")),
+        categories: get_list(fields.get("Categories").copied()),
+        working_directory: get("Path").filter(|p| !p.is_empty()).map(PathBuf::from),
+        name,
+        icon,
+        exec,
+        terminal,
+        path,
+    })
+}
+
+/// The user's locale (from `$LC_ALL`, `$LC_MESSAGES`, or `$LANG`), used to pick localized values
+/// (e.g. `Name[de_DE]`)
+struct Locale {
+    lang: Option<String>,
+    country: Option<String>,
+    modifier: Option<String>,
+}
+
+impl Locale {
+    fn from_env() -> Self {
+        let value = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .find_map(|var| env::var(var).ok().filter(|v| !v.is_empty()))
+            .unwrap_or_default();
+        Self::parse(&value)
+    }
+
+    /// Parse a locale of the form `lang_COUNTRY.ENCODING@MODIFIER`
+    fn parse(value: &str) -> Self {
+        let (value, modifier) = match value.split_once('@') {
+            Some((value, modifier)) => (value, Some(modifier.to_string())),
+            None => (value, None),
+        };
+        let value = value.split('.').next().unwrap_or_default();
+        let (lang, country) = match value.split_once('_') {
+            Some((lang, country)) => (lang, Some(country.to_string())),
+            None => (value, None),
+        };
+        let lang = (!lang.is_empty() && lang != "C" && lang != "POSIX").then(|| lang.to_string());
+        Self {
+            lang,
+            country,
+            modifier,
+        }
+    }
+
+    /// The keys to look up for a localized value, from most to least specific
+    fn keys(&self, key: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        if let Some(lang) = &self.lang {
+            if let (Some(country), Some(modifier)) = (&self.country, &self.modifier) {
+                keys.push(format!("{key}[{lang}_{country}@{modifier}]"));
+            }
+            if let Some(country) = &self.country {
+                keys.push(format!("{key}[{lang}_{country}]"));
+            }
+            if let Some(modifier) = &self.modifier {
+                keys.push(format!("{key}[{lang}@{modifier}]"));
+            }
+            keys.push(format!("{key}[{lang}]"));
+        }
+        keys.push(key.to_string());
+        keys
+    }
+}
+
+/// Unescape a value (`\s`, `\n`, `\t`, `\r`, and `\\`)
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            // Leave unknown escapes as they are
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Split a `;`-separated list (where `\;` is an escaped `;`). Other escapes are kept, so that
+/// the items can be unescaped afterwards.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(';') => item.push(';'),
+                Some(c) => item.extend(['\\', c]),
+                None => item.push('\\'),
+            },
+            ';' => items.push(std::mem::take(&mut item)),
+            c => item.push(c),
+        }
+    }
+    items.push(item);
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Split an `Exec` value into arguments, handling double-quoted arguments (returns `None` if the
+/// value is malformed)
+fn split_exec(exec: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            '\\' if in_quotes => arg.push(chars.next()?),
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_quotes {
+        return None;
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Some(args)
+}
+
+/// Expand the field codes in the arguments of an `Exec` value. No files or URLs are passed to the
+/// application, so `%f`, `%F`, `%u`, and `%U` are removed.
+fn expand_field_codes(args: &[String], name: &str, icon: Option<&str>, path: &Path) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "%f" | "%F" | "%u" | "%U" => {}
+            "%i" => {
+                if let Some(icon) = icon {
+                    expanded.extend(["--icon".to_string(), icon.to_string()]);
+                }
+            }
+            _ => {
+                let mut result = String::new();
+                let mut chars = arg.chars();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        result.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('%') => result.push('%'),
+                        Some('c') => result.push_str(name),
+                        Some('k') => result.push_str(&path.to_string_lossy()),
+                        // Other field codes are either deprecated, or refer to files
+                        _ => {}
+                    }
+                }
+                expanded.push(result);
+            }
+        }
+    }
+    expanded
+}
+
+/// Wrap a command so that it runs in a terminal emulator
+fn wrap_in_terminal(exec: Vec<String>) -> Option<Vec<String>> {
+    let terminal = env::var("TERMINAL")
+        .ok()
+        .filter(|t| !t.is_empty())
+        .or_else(|| {
+            TERMINALS
+                .iter()
+                .find(|t| find_executable(t).is_some())
+                .map(|t| t.to_string())
+        })?;
+    let separator = match Path::new(&terminal).file_name()?.to_str()? {
+        "xdg-terminal-exec" => None,
+        "gnome-terminal" => Some("--"),
+        _ => Some("-e"),
+    };
+    let mut wrapped = vec![terminal.clone()];
+    wrapped.extend(separator.map(str::to_string));
+    wrapped.extend(exec);
+    Some(wrapped)
+}
+
+/// Find an executable, either by its absolute path or in `$PATH`
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn split_exec_handles_quotes() {
+        assert_eq!(
+            split_exec(r#"app --flag "two words" "quoted \"arg\"" %U"#),
+            Some(strings(&[
+                "app",
+                "--flag",
+                "two words",
+                r#"quoted "arg""#,
+                "%U"
+            ]))
+        );
+        assert_eq!(split_exec(r#"app """#), Some(strings(&["app", ""])));
+        assert_eq!(split_exec("  app   arg  "), Some(strings(&["app", "arg"])));
+        assert_eq!(split_exec(r#"app "unterminated"#), None);
+    }
+
+    #[test]
+    fn expand_field_codes_removes_files_and_expands_the_rest() {
+        let path = Path::new("/usr/share/applications/app.desktop");
+        let args = strings(&["app", "%f", "%U", "%i", "--name=%c", "%k", "100%%", "%d"]);
+        assert_eq!(
+            expand_field_codes(&args, "App", Some("app-icon"), path),
+            strings(&[
+                "app",
+                "--icon",
+                "app-icon",
+                "--name=App",
+                "/usr/share/applications/app.desktop",
+                "100%",
+                "",
+            ])
+        );
+        assert_eq!(
+            expand_field_codes(&strings(&["app", "%i"]), "App", None, path),
+            strings(&["app"])
+        );
+    }
+
+    #[test]
+    fn locale_keys_go_from_most_to_least_specific() {
+        assert_eq!(
+            Locale::parse("de_DE.UTF-8@euro").keys("Name"),
+            strings(&[
+                "Name[de_DE@euro]",
+                "Name[de_DE]",
+                "Name[de@euro]",
+                "Name[de]",
+                "Name"
+            ])
+        );
+        assert_eq!(
+            Locale::parse("fr").keys("Name"),
+            strings(&["Name[fr]", "Name"])
+        );
+        assert_eq!(Locale::parse("C.UTF-8").keys("Name"), strings(&["Name"]));
+        assert_eq!(Locale::parse("").keys("Name"), strings(&["Name"]));
+    }
+
+    #[test]
+    fn unescape_values() {
+        assert_eq!(unescape(r"a\sb\tc\nd\\e"), "a b\tc\nd\\e");
+        assert_eq!(unescape(r"keep\;this\"), r"keep\;this\");
+    }
+
+    #[test]
+    fn lists_are_split_before_being_unescaped() {
+        let fields = parse_desktop_entry(concat!(
+            "[Desktop Entry]\nType=Application\nName=App\nExec=app\n",
+            r"Keywords=a\;b;c\\;d\s;;",
+            "\nCategories=Utility;",
+        ));
+        let entry = to_desktop_entry(
+            "app.desktop".into(),
+            PathBuf::from("app.desktop"),
+            &fields,
+            &Locale::parse(""),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(entry.keywords, strings(&["a;b", "c\\", "d "]));
+        assert_eq!(entry.categories, strings(&["Utility"]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn desktop_files_survive_symlink_cycles() {
+        let dir = env::temp_dir().join(format!("polymenu-desktop-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/app.desktop"), "").unwrap();
+        let _ = std::os::unix::fs::symlink(&dir, dir.join("sub/loop"));
+        let ids: Vec<_> = desktop_files(&dir).into_iter().map(|(id, _)| id).collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ids, strings(&["sub-app.desktop"]));
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::watch::WatchEvent;
+
+/// How many events can be queued for a slow subscriber before it starts missing events
+const CAPACITY: usize = 64;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An event sent between the app's subsystems and the webview
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum Event {
+    /// An input or mount changed (only sent when watching is enabled)
+    Watch(WatchEvent),
+    /// A command started running
+    CommandStarted { name: String },
+    /// A command finished running
+    CommandFinished { name: String, success: bool },
+    /// The app is about to shut down
+    Shutdown,
+    /// A message sent by the webview was invalid
+    Error { message: String },
+    /// An app-defined event (the only kind of event the webview can send)
+    Custom { name: String, data: Value },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Server,
+    Client,
+}
+
+/// The message format of the `/api/events` WebSocket
+#[derive(Debug, Clone, Serialize)]
+pub struct Envelope {
+    #[serde(flatten)]
+    pub event: Event,
+    pub source: Source,
+    /// The WebSocket connection that sent the event, so it isn't echoed back
+    #[serde(skip)]
+    connection: Option<u64>,
+}
+
+/// Broadcasts events to every subscriber (including every connected webview)
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Envelope>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(Envelope {
+            event,
+            source: Source::Server,
+            connection: None,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.sender.subscribe()
+    }
+
+    /// Publish each event received from `receiver` (until it closes)
+    pub fn forward<T: Clone + Send + 'static>(
+        &self,
+        mut receiver: broadcast::Receiver<T>,
+        into_event: fn(T) -> Event,
+    ) {
+        let bus = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => bus.publish(into_event(event)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Send every event to a webview over its WebSocket, and publish the custom events it sends,
+    /// until either side closes the connection or the app shuts down
+    pub async fn serve(&self, socket: WebSocket, shutdown_token: CancellationToken) {
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+        let (mut sink, mut stream) = socket.split();
+        let mut receiver = self.subscribe();
+        let send = |envelope: &Envelope| {
+            serde_json::to_string(envelope)
+                .map(|json| Message::Text(json.into()))
+                .expect("events can always be serialized")
+        };
+        loop {
+            tokio::select! {
+                _ = shutdown_token.cancelled() => {
+                    let envelope = Envelope {
+                        event: Event::Shutdown,
+                        source: Source::Server,
+                        connection: None,
+                    };
+                    let _ = sink.send(send(&envelope)).await;
+                    let _ = sink.close().await;
+                    break;
+                }
+                envelope = receiver.recv() => match envelope {
+                    Ok(envelope) if envelope.connection == Some(id) => {}
+                    Ok(envelope) => {
+                        if sink.send(send(&envelope)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                message = stream.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<Event>(&text) {
+                            Ok(event @ Event::Custom { .. }) => {
+                                let _ = self.sender.send(Envelope {
+                                    event,
+                                    source: Source::Client,
+                                    connection: Some(id),
+                                });
+                            }
+                            result => {
+                                let message = match result {
+                                    Ok(_) => "the webview can only send `custom` events".to_string(),
+                                    Err(e) => format!("invalid event: {e}"),
+                                };
+                                let envelope = Envelope {
+                                    event: Event::Error { message },
+                                    source: Source::Server,
+                                    connection: None,
+                                };
+                                if sink.send(send(&envelope)).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+}
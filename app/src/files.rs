@@ -0,0 +1,274 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::MountConfig;
+
+type Result<T> = std::result::Result<T, (StatusCode, String)>;
+
+/// A mounted directory, and the rules for which of its files the webview can access
+pub struct Mount {
+    pub root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    pub hidden: bool,
+    pub follow_symlinks: bool,
+}
+
+impl Mount {
+    pub fn new(root: &Path, config: &MountConfig) -> anyhow::Result<Self> {
+        let root = std::fs::canonicalize(root)
+            .with_context(|| format!("could not resolve mount path: {root:?}"))?;
+        let include = (!config.include.is_empty())
+            .then(|| glob_set(&config.include))
+            .transpose()?;
+        Ok(Self {
+            root,
+            include,
+            exclude: glob_set(&config.exclude)?,
+            hidden: config.hidden,
+            follow_symlinks: config.follow_symlinks,
+        })
+    }
+
+    /// Whether the webview can access a path (relative to the mount). Directories are allowed
+    /// unless they are hidden or excluded, so that included files can be found within them.
+    pub fn allows(&self, relative: &Path) -> bool {
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return false;
+        }
+        for ancestor in relative.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+            let hidden = ancestor
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            let is_symlink = || {
+                std::fs::symlink_metadata(self.root.join(ancestor))
+                    .is_ok_and(|m| m.file_type().is_symlink())
+            };
+            if (hidden && !self.hidden)
+                || self.exclude.is_match(ancestor)
+                || (!self.follow_symlinks && is_symlink())
+            {
+                return false;
+            }
+        }
+        match &self.include {
+            Some(include) => self.root.join(relative).is_dir() || include.is_match(relative),
+            None => true,
+        }
+    }
+
+    /// Resolve a path requested by the webview to a path inside the mount, rejecting any path
+    /// that the webview can't access, or that could escape the mount (through `..` or a
+    /// symlinked directory)
+    pub async fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path);
+        if path.is_empty() || !self.allows(relative) {
+            return Err((StatusCode::NOT_FOUND, format!("File not found: {path}")));
+        }
+        let resolved = self.root.join(relative);
+        // The file itself may not exist yet, so check the closest directory that does
+        let mut ancestor = resolved.parent();
+        while let Some(dir) = ancestor {
+            if let Ok(dir) = fs::canonicalize(dir).await {
+                if dir.starts_with(&self.root) {
+                    return Ok(resolved);
+                }
+                break;
+            }
+            ancestor = dir.parent();
+        }
+        Err((StatusCode::BAD_REQUEST, format!("Invalid path: {path}")))
+    }
+}
+
+fn glob_set(globs: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).with_context(|| format!("invalid glob: {glob}"))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Atomically create or replace a file, creating its parent directories if needed
+pub async fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    if fs::metadata(path).await.is_ok_and(|m| m.is_dir()) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Cannot overwrite a directory: {path:?}"),
+        ));
+    }
+    let dir = path.parent().ok_or_else(|| internal_error("no parent"))?;
+    fs::create_dir_all(dir).await.map_err(internal_error)?;
+    // Write to a temporary file first, so that the file is never left partially written
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = dir.join(format!(".{file_name}.{:x}.tmp", rand::random::<u64>()));
+    let result = async {
+        let mut file = fs::File::create(&tmp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path).await;
+    }
+    result.map_err(internal_error)
+}
+
+/// Delete a file, or an empty directory
+pub async fn delete(path: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).await.map_err(not_found(path))?;
+    if metadata.is_dir() {
+        fs::remove_dir(path).await.map_err(|e| {
+            (
+                StatusCode::CONFLICT,
+                format!("Could not delete directory {path:?}: {e}"),
+            )
+        })
+    } else {
+        fs::remove_file(path).await.map_err(internal_error)
+    }
+}
+
+/// Move a file or directory, without replacing any existing file at the destination
+pub async fn rename(from: &Path, to: &Path) -> Result<()> {
+    fs::symlink_metadata(from).await.map_err(not_found(from))?;
+    if fs::symlink_metadata(to).await.is_ok() {
+        return Err((StatusCode::CONFLICT, format!("File already exists: {to:?}")));
+    }
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).await.map_err(internal_error)?;
+    }
+    fs::rename(from, to).await.map_err(internal_error)
+}
+
+fn not_found(path: &Path) -> impl FnOnce(std::io::Error) -> (StatusCode, String) {
+    move |_| (StatusCode::NOT_FOUND, format!("File not found: {path:?}"))
+}
+
+fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mount of a fresh directory containing `files` (which is removed when dropped)
+    struct TestMount {
+        mount: Mount,
+    }
+
+    impl TestMount {
+        fn new(name: &str, files: &[&str], config: MountConfig) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("polymenu-mount-test-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            for file in files {
+                let path = root.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, "").unwrap();
+            }
+            Self {
+                mount: Mount::new(&root, &config).unwrap(),
+            }
+        }
+
+        fn allows(&self, path: &str) -> bool {
+            self.mount.allows(Path::new(path))
+        }
+    }
+
+    impl Drop for TestMount {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.mount.root);
+        }
+    }
+
+    #[test]
+    fn paths_outside_the_mount_are_denied() {
+        let mount = TestMount::new("outside", &["a.txt"], MountConfig::default());
+        assert!(mount.allows("a.txt"));
+        assert!(mount.allows("./a.txt"));
+        assert!(mount.allows("missing.txt"));
+        assert!(!mount.allows("../a.txt"));
+        assert!(!mount.allows("dir/../../a.txt"));
+        assert!(!mount.allows("/etc/passwd"));
+    }
+
+    #[test]
+    fn hidden_files_are_denied_by_default() {
+        let files = &[".env", ".git/config", "dir/.secret"];
+        let mount = TestMount::new("hidden", files, MountConfig::default());
+        assert!(!mount.allows(".env"));
+        assert!(!mount.allows(".git/config"));
+        assert!(!mount.allows("dir/.secret"));
+        assert!(mount.allows("dir"));
+
+        let config = MountConfig {
+            hidden: true,
+            ..Default::default()
+        };
+        let mount = TestMount::new("hidden-allowed", files, config);
+        assert!(mount.allows(".env"));
+        assert!(mount.allows(".git/config"));
+    }
+
+    #[test]
+    fn excluded_directories_exclude_their_contents() {
+        let config = MountConfig {
+            exclude: vec!["private".into(), "**/*.key".into()],
+            ..Default::default()
+        };
+        let files = &["private/a.txt", "public/a.txt", "public/b.key"];
+        let mount = TestMount::new("exclude", files, config);
+        assert!(!mount.allows("private"));
+        assert!(!mount.allows("private/a.txt"));
+        assert!(!mount.allows("public/b.key"));
+        assert!(mount.allows("public/a.txt"));
+    }
+
+    #[test]
+    fn only_included_files_are_allowed() {
+        let config = MountConfig {
+            include: vec!["**/*.png".into()],
+            ..Default::default()
+        };
+        let files = &["a.png", "dir/b.png", "dir/index.html", "c.txt"];
+        let mount = TestMount::new("include", files, config);
+        assert!(mount.allows("a.png"));
+        assert!(mount.allows("dir/b.png"));
+        // Directories are allowed, so that included files can be listed
+        assert!(mount.allows("dir"));
+        assert!(!mount.allows("dir/index.html"));
+        assert!(!mount.allows("c.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_only_followed_if_enabled() {
+        let mount = TestMount::new("symlinks", &["dir/a.txt"], MountConfig::default());
+        let root = &mount.mount.root;
+        std::os::unix::fs::symlink(root.join("dir"), root.join("link")).unwrap();
+        assert!(mount.allows("dir/a.txt"));
+        assert!(!mount.allows("link"));
+        assert!(!mount.allows("link/a.txt"));
+
+        let config = MountConfig {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let mount = TestMount::new("symlinks-followed", &["dir/a.txt"], config);
+        let root = &mount.mount.root;
+        std::os::unix::fs::symlink(root.join("dir"), root.join("link")).unwrap();
+        assert!(mount.allows("link/a.txt"));
+    }
+}
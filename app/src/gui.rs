@@ -1,23 +1,47 @@
 #[cfg(target_os = "linux")]
 use anyhow::anyhow;
+use axum::Router;
+use std::sync::{Arc, RwLock};
 #[cfg(target_os = "linux")]
 use tao::dpi::PhysicalSize;
 use tao::{
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     platform::run_return::EventLoopExtRunReturn,
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
+use tokio::{runtime::Handle, sync::watch};
 use tokio_util::sync::CancellationToken;
-use wry::WebViewBuilder;
+use wry::{NewWindowResponse, WebViewBuilder};
 
-use crate::{config::Config, server::AUTH_TOKEN};
+use crate::{
+    auth::WindowToken,
+    config::{Config, WindowOptions},
+    protocol::{SCHEME, serve_in_process},
+    security::{Network, allows_navigation},
+};
 
 #[derive(Debug, Clone)]
 pub enum AppEvent {
     Shutdown,
+    /// Show the app that the daemon has just swapped in (the daemon's window is hidden when
+    /// `session` is cancelled)
+    Show {
+        session: CancellationToken,
+        window: WindowOptions,
+        network: Network,
+    },
+    /// Hide the window (of the daemon, or of a resident app)
+    Hide,
+    /// Show the window of a resident app again
+    Reopen,
 }
 
+#[cfg(target_os = "linux")]
+type Overlay = Option<gtk::ApplicationWindow>;
+#[cfg(not(target_os = "linux"))]
+type Overlay = Option<()>;
+
 #[cfg(target_os = "linux")]
 fn is_wayland_window(window: &tao::window::Window) -> bool {
     use gtk::{glib::ObjectExt, traits::WidgetExt};
@@ -66,12 +90,54 @@ fn setup_wayland_layer_shell(
     Ok(overlay_window)
 }
 
+/// Show (or hide) the window, or its layer shell overlay on Wayland
+fn set_visible(window: &Window, overlay: &Overlay, visible: bool) {
+    #[cfg(target_os = "linux")]
+    if let Some(overlay) = overlay {
+        use gtk::prelude::WidgetExt;
+        if visible {
+            overlay.show_all();
+        } else {
+            overlay.hide();
+        }
+        return;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = overlay;
+    window.set_visible(visible);
+}
+
+/// Apply the window options of the app that the daemon is about to show
+fn apply_window_options(window: &Window, overlay: &Overlay, options: &WindowOptions) {
+    if let Some(size) = options.size() {
+        window.set_inner_size(size);
+        #[cfg(target_os = "linux")]
+        if let Some(overlay) = overlay {
+            use gtk::prelude::WidgetExt;
+            overlay.set_size_request(size.width as i32, size.height as i32);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = overlay;
+    if let Some(position) = options.position() {
+        window.set_outer_position(position);
+    }
+    window.set_decorations(options.decorations);
+}
+
+/// Run the window until the app shuts down. The window's token (if the server authenticates
+/// requests) is invalidated once the window is closed.
 pub fn run_gui(
     config: &Config,
+    mut window_token: Option<WindowToken>,
     mut event_loop: EventLoop<AppEvent>,
     shutdown_token: CancellationToken,
+    runtime: &Handle,
+    app: watch::Receiver<Option<Router>>,
 ) -> anyhow::Result<()> {
+    // The daemon's window is only shown when it is showing an app
     let mut window = WindowBuilder::new()
+        .with_visible(!config.daemon)
         .with_transparent(!config.window.opaque)
         .with_decorations(config.window.decorations)
         .with_focused(!config.window.no_focus);
@@ -83,17 +149,60 @@ pub fn run_gui(
     }
 
     let window = window.build(&event_loop).unwrap();
-    let builder = WebViewBuilder::new()
+    let mut builder = WebViewBuilder::new()
         .with_transparent(!config.window.opaque)
-        .with_devtools(true)
-        .with_url(config.gui_target_url())
-        .with_initialization_script(format!(r#"window.__AUTH_TOKEN__ = "{}";"#, *AUTH_TOKEN));
+        .with_devtools(config.devtools || config.develop);
+    if let Some(token) = &window_token {
+        builder = builder.with_initialization_script(format!(
+            r#"window.__AUTH_TOKEN__ = "{}";"#,
+            token.as_str()
+        ));
+    }
+    let url = config.gui_target_url();
+    if !config.daemon {
+        builder = builder.with_url(&url);
+    }
+    // Shared with the navigation handlers, since the daemon changes it whenever it shows an app
+    let network = Arc::new(RwLock::new(config.network.clone().unwrap_or_default()));
+    builder = builder
+        .with_navigation_handler({
+            let (url, network) = (url.clone(), network.clone());
+            move |target| allows_navigation(&url, &network.read().unwrap(), &target)
+        })
+        .with_new_window_req_handler({
+            let (url, network) = (url.clone(), network.clone());
+            move |target, _| {
+                if allows_navigation(&url, &network.read().unwrap(), &target) {
+                    NewWindowResponse::Allow
+                } else {
+                    NewWindowResponse::Deny
+                }
+            }
+        });
+    if config.in_process && !config.develop {
+        builder = builder.with_asynchronous_custom_protocol(
+            SCHEME.to_string(),
+            serve_in_process(app, runtime.clone()),
+        );
+    }
+    #[cfg(unix)]
+    if config.socket && !config.develop {
+        use crate::protocol::forward_to_socket;
+        builder = builder.with_asynchronous_custom_protocol(
+            SCHEME.to_string(),
+            forward_to_socket(
+                config.socket_path(),
+                window_token.as_ref().map(|t| t.as_str().to_string()),
+                runtime.clone(),
+            ),
+        );
+    }
 
     #[cfg(any(target_os = "windows", target_os = "macos"))]
-    let _webview = builder.build(&window)?;
+    let (webview, overlay_window): (_, Overlay) = (builder.build(&window)?, None);
 
     #[cfg(target_os = "linux")]
-    let (_webview, _overlay_window) = {
+    let (webview, overlay_window) = {
         use tao::platform::unix::WindowExtUnix;
         use wry::WebViewBuilderExtUnix;
         let vbox = window.default_vbox().unwrap();
@@ -104,6 +213,14 @@ pub fn run_gui(
         (webview, overlay_window)
     };
 
+    if config.daemon {
+        set_visible(&window, &overlay_window, false);
+    }
+
+    let daemon = config.daemon;
+    let resident = config.resident;
+    let no_focus = config.window.no_focus;
+    let mut session: Option<CancellationToken> = None;
     let code = event_loop.run_return(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
 
@@ -112,8 +229,53 @@ pub fn run_gui(
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
-                // Shutdown the server and dev server if they haven't been shutdown already
-                shutdown_token.cancel();
+                if daemon {
+                    // Close the app that is being shown, but keep the daemon running
+                    set_visible(&window, &overlay_window, false);
+                    if let Some(session) = session.take() {
+                        session.cancel();
+                    }
+                } else if resident {
+                    set_visible(&window, &overlay_window, false);
+                } else {
+                    // The window's credentials can't be used anymore, even while the server is
+                    // shutting down
+                    window_token.take();
+                    // Shutdown the server and dev server if they haven't been shutdown already
+                    shutdown_token.cancel();
+                }
+            }
+            Event::UserEvent(AppEvent::Show {
+                session: new_session,
+                window: options,
+                network: app_network,
+            }) => {
+                apply_window_options(&window, &overlay_window, &options);
+                *network.write().unwrap() = app_network;
+                // Load the app from scratch, since it was just swapped in
+                if let Err(e) = webview.load_url(&url) {
+                    eprintln!("could not load app: {e}");
+                }
+                set_visible(&window, &overlay_window, true);
+                if !options.no_focus {
+                    window.set_focus();
+                }
+                session = Some(new_session);
+            }
+            Event::UserEvent(AppEvent::Hide) => {
+                set_visible(&window, &overlay_window, false);
+                // Unload the daemon's app, which can't be used anymore (a resident app is
+                // shown again as it was)
+                if daemon && let Err(e) = webview.load_url("about:blank") {
+                    eprintln!("could not unload app: {e}");
+                }
+                session = None;
+            }
+            Event::UserEvent(AppEvent::Reopen) => {
+                set_visible(&window, &overlay_window, true);
+                if !no_focus {
+                    window.set_focus();
+                }
             }
             Event::UserEvent(AppEvent::Shutdown) => {
                 // Exit the application & close the GUI window
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::desktop::desktop_entries;
+use crate::xdg::{data_dirs, parse_key_file};
+
+/// Icon file extensions (in order of preference) that can be displayed by the webview
+const EXTENSIONS: &[&str] = &["svg", "png"];
+
+const FALLBACK_THEME: &str = "hicolor";
+
+/// How many lookups are cached before the cache is cleared (sizes come from the webview, so there
+/// could be any number of them)
+const MAX_CACHED_LOOKUPS: usize = 1024;
+
+/// Resolves icon names (e.g. `firefox`) to files, following the freedesktop icon theme spec
+pub struct IconLookup {
+    base_dirs: Vec<PathBuf>,
+    /// The current theme, followed by the themes it inherits from (and the fallback theme)
+    themes: Vec<Theme>,
+    cache: Mutex<HashMap<(String, u32, u32), Option<PathBuf>>>,
+    /// The icons of installed applications that are given as absolute paths (re-read whenever an
+    /// icon isn't found in it, since applications can be installed while polymenu is running)
+    application_icons: Mutex<HashSet<PathBuf>>,
+}
+
+impl IconLookup {
+    /// Load the given icon theme (or the current user's theme, if not provided)
+    pub fn new(theme: Option<String>) -> Self {
+        let base_dirs = base_dirs();
+        let theme = theme
+            .or_else(current_theme)
+            .unwrap_or_else(|| FALLBACK_THEME.to_string());
+
+        let mut themes = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![theme];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let Some(theme) = Theme::load(&name, &base_dirs) else {
+                continue;
+            };
+            // Search inherited themes depth-first, in the order they are listed
+            pending.extend(theme.inherits.iter().rev().cloned());
+            themes.push(theme);
+        }
+        if !seen.contains(FALLBACK_THEME)
+            && let Some(theme) = Theme::load(FALLBACK_THEME, &base_dirs)
+        {
+            themes.push(theme);
+        }
+
+        Self {
+            base_dirs,
+            themes,
+            cache: Mutex::new(HashMap::new()),
+            application_icons: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Find the file for an icon that best matches the requested size and scale
+    pub fn find(&self, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        if Path::new(name).is_absolute() {
+            return self.find_absolute(Path::new(name));
+        }
+        let key = (name.to_string(), size, scale);
+        if let Some(path) = self.cache.lock().unwrap().get(&key) {
+            return path.clone();
+        }
+        let path = self
+            .themes
+            .iter()
+            .find_map(|theme| self.find_in_theme(theme, name, size, scale))
+            .or_else(|| self.find_unthemed(name));
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED_LOOKUPS {
+            cache.clear();
+        }
+        cache.insert(key, path.clone());
+        path
+    }
+
+    /// An icon given as an absolute path (which desktop entries can use instead of a name). Only
+    /// the icons of installed applications are served, so that the webview can't read arbitrary
+    /// files.
+    fn find_absolute(&self, path: &Path) -> Option<PathBuf> {
+        let mut icons = self.application_icons.lock().unwrap();
+        if !icons.contains(path) {
+            *icons = desktop_entries()
+                .into_iter()
+                .filter_map(|entry| entry.icon.map(PathBuf::from))
+                .filter(|icon| icon.is_absolute())
+                .collect();
+        }
+        (icons.contains(path) && path.is_file()).then(|| path.to_path_buf())
+    }
+
+    fn find_in_theme(&self, theme: &Theme, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        let candidates = || {
+            theme.dirs.iter().flat_map(move |dir| {
+                self.base_dirs.iter().flat_map(move |base| {
+                    EXTENSIONS.iter().map(move |ext| {
+                        let path = base
+                            .join(&theme.name)
+                            .join(&dir.path)
+                            .join(format!("{name}.{ext}"));
+                        (dir, path)
+                    })
+                })
+            })
+        };
+        candidates()
+            .find(|(dir, path)| dir.matches_size(size, scale) && path.is_file())
+            .or_else(|| {
+                candidates()
+                    .filter(|(_, path)| path.is_file())
+                    .min_by_key(|(dir, _)| dir.size_distance(size, scale))
+            })
+            .map(|(_, path)| path)
+    }
+
+    /// Icons that aren't part of any theme (e.g. in `/usr/share/pixmaps`)
+    fn find_unthemed(&self, name: &str) -> Option<PathBuf> {
+        self.base_dirs
+            .iter()
+            .flat_map(|base| {
+                EXTENSIONS
+                    .iter()
+                    .map(move |ext| base.join(format!("{name}.{ext}")))
+            })
+            .find(|path| path.is_file())
+    }
+}
+
+struct Theme {
+    name: String,
+    inherits: Vec<String>,
+    dirs: Vec<ThemeDir>,
+}
+
+impl Theme {
+    /// Load a theme from the first `index.theme` found in the base directories
+    fn load(name: &str, base_dirs: &[PathBuf]) -> Option<Self> {
+        let src = base_dirs
+            .iter()
+            .find_map(|base| std::fs::read_to_string(base.join(name).join("index.theme")).ok())?;
+        let sections = parse_key_file(&src);
+        let index = sections.get("Icon Theme")?;
+        let list = |key: &str| {
+            index
+                .get(key)
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let inherits: Vec<String> = list("Inherits");
+        let directories: Vec<String> = list("Directories");
+        let scaled_directories: Vec<String> = list("ScaledDirectories");
+        let dirs = directories
+            .into_iter()
+            .chain(scaled_directories)
+            .filter_map(|path| {
+                let section = sections.get(path.as_str())?;
+                ThemeDir::new(path, section)
+            })
+            .collect();
+        Some(Self {
+            name: name.to_string(),
+            inherits,
+            dirs,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SizeKind {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+struct ThemeDir {
+    path: String,
+    size: u32,
+    scale: u32,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+    kind: SizeKind,
+}
+
+impl ThemeDir {
+    fn new(path: String, section: &HashMap<&str, &str>) -> Option<Self> {
+        let number = |key: &str| section.get(key).and_then(|v| v.parse::<u32>().ok());
+        let size = number("Size")?;
+        let kind = match section.get("Type").copied() {
+            Some("Fixed") => SizeKind::Fixed,
+            Some("Scalable") => SizeKind::Scalable,
+            _ => SizeKind::Threshold,
+        };
+        Some(Self {
+            path,
+            size,
+            scale: number("Scale").unwrap_or(1),
+            min_size: number("MinSize").unwrap_or(size),
+            max_size: number("MaxSize").unwrap_or(size),
+            threshold: number("Threshold").unwrap_or(2),
+            kind,
+        })
+    }
+
+    fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+        match self.kind {
+            SizeKind::Fixed => self.size == size,
+            SizeKind::Scalable => (self.min_size..=self.max_size).contains(&size),
+            SizeKind::Threshold => (self.size.saturating_sub(self.threshold)
+                ..=self.size.saturating_add(self.threshold))
+                .contains(&size),
+        }
+    }
+
+    fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let (min, max) = match self.kind {
+            SizeKind::Fixed => (self.size, self.size),
+            SizeKind::Scalable => (self.min_size, self.max_size),
+            SizeKind::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size.saturating_add(self.threshold),
+            ),
+        };
+        let target = size.saturating_mul(scale);
+        let (min, max) = (
+            min.saturating_mul(self.scale),
+            max.saturating_mul(self.scale),
+        );
+        min.saturating_sub(target).max(target.saturating_sub(max))
+    }
+}
+
+/// `$HOME/.icons`, the `icons` directory of each XDG data directory, and `/usr/share/pixmaps`
+fn base_dirs() -> Vec<PathBuf> {
+    dirs::home_dir()
+        .map(|home| home.join(".icons"))
+        .into_iter()
+        .chain(data_dirs().into_iter().map(|dir| dir.join("icons")))
+        .chain([PathBuf::from("/usr/share/pixmaps")])
+        .collect()
+}
+
+/// The user's icon theme, as configured for GTK or KDE
+fn current_theme() -> Option<String> {
+    let config_dir = dirs::config_dir()?;
+    let lookup = |file: &str, section: &str, key: &str| {
+        let src = std::fs::read_to_string(config_dir.join(file)).ok()?;
+        let sections = parse_key_file(&src);
+        let value = sections.get(section)?.get(key)?;
+        Some(value.trim_matches('"').to_string())
+    };
+    lookup("gtk-4.0/settings.ini", "Settings", "gtk-icon-theme-name")
+        .or_else(|| lookup("gtk-3.0/settings.ini", "Settings", "gtk-icon-theme-name"))
+        .or_else(|| lookup("kdeglobals", "Icons", "Theme"))
+}
+
+/// The MIME type of an icon file
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("svg") => "image/svg+xml",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("xpm") => "image/x-xpixmap",
+        _ => "image/png",
+    }
+}
@@ -1,21 +1,25 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
 use clap::ValueEnum;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 
 use crate::command::Command;
-use crate::config::Config;
+use crate::config::InputConfig;
+use crate::desktop::desktop_entries;
 use crate::expansion::expand_path;
+use crate::schema::Schema;
+use crate::transform::Transform;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Cursor, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-static STDIN_CONTENT: Lazy<String> = Lazy::new(|| {
-    let mut s = String::new();
-    io::stdin().read_to_string(&mut s).unwrap();
-    s
+static STDIN_CONTENT: Lazy<io::Result<Vec<u8>>> = Lazy::new(|| {
+    let mut buf = Vec::new();
+    io::stdin().read_to_end(&mut buf).map(|_| buf)
 });
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
@@ -31,6 +35,10 @@ pub enum IOFormat {
     JsonLines,
     /// Raw (will be read as a string)
     Raw,
+    /// Markdown pipe table (will be converted to JSON objects for each row)
+    MarkdownTable,
+    /// Org-mode table (will be converted to JSON objects for each row)
+    OrgTable,
 }
 
 impl Default for IOFormat {
@@ -39,11 +47,191 @@ impl Default for IOFormat {
     }
 }
 
+impl IOFormat {
+    /// Infer the format of a file from its extension, looking past any compression suffix
+    /// (e.g. `foo.jsonl.gz` is read as JSON lines)
+    pub fn from_path(path: &Path) -> Self {
+        let mut extension = path.extension().and_then(|e| e.to_str());
+        if extension.is_some_and(|e| Compression::from_extension(e).is_some()) {
+            extension = path
+                .file_stem()
+                .and_then(|s| Path::new(s).extension())
+                .and_then(|e| e.to_str());
+        }
+        match extension {
+            Some("csv") => IOFormat::Csv,
+            Some("json") => IOFormat::Json,
+            Some("jsonl") => IOFormat::JsonLines,
+            _ => IOFormat::Raw,
+        }
+    }
+}
+
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// UTF-8 (default; invalid input is an error)
+    #[default]
+    #[serde(alias = "utf-8")]
+    #[value(alias = "utf-8")]
+    Utf8,
+    /// UTF-16 (byte order is detected from the byte order mark, defaulting to little-endian)
+    #[serde(alias = "utf-16")]
+    #[value(alias = "utf-16")]
+    Utf16,
+    /// Latin-1 / ISO-8859-1 (every byte is a valid character)
+    #[serde(alias = "iso-8859-1")]
+    #[value(alias = "iso-8859-1")]
+    Latin1,
+    /// UTF-8, replacing invalid sequences with `�`
+    Lossy,
+    /// Binary data, passed through as a base64 string (only with the `raw` format)
+    Base64,
+}
+
+impl Encoding {
+    const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+
+    pub fn decode(self, mut bytes: Vec<u8>) -> Result<String> {
+        match self {
+            Self::Utf8 => {
+                if bytes.starts_with(Self::UTF8_BOM) {
+                    bytes.drain(..Self::UTF8_BOM.len());
+                }
+                String::from_utf8(bytes).map_err(|e| {
+                    anyhow!(
+                        "input is not valid UTF-8 ({e}); set `encoding` to decode it differently"
+                    )
+                })
+            }
+            Self::Utf16 => decode_utf16(&bytes),
+            Self::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            Self::Lossy => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            Self::Base64 => Ok(BASE64_STANDARD.encode(bytes)),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8]) -> Result<String> {
+    let (bytes, big_endian) = match bytes {
+        [0xfe, 0xff, rest @ ..] => (rest, true),
+        [0xff, 0xfe, rest @ ..] => (rest, false),
+        _ => (bytes, false),
+    };
+    let (pairs, remainder) = bytes.as_chunks::<2>();
+    if !remainder.is_empty() {
+        bail!("input is not valid UTF-16 (odd number of bytes)");
+    }
+    let units = pairs.iter().map(|&pair| {
+        if big_endian {
+            u16::from_be_bytes(pair)
+        } else {
+            u16::from_le_bytes(pair)
+        }
+    });
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| anyhow!("input is not valid UTF-16 ({e})"))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+    const XZ_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "xz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    pub fn from_magic_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(Self::GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if bytes.starts_with(Self::ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else if bytes.starts_with(Self::XZ_MAGIC) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub fn decoder<R: BufRead + 'static>(self, source: R) -> Result<Box<dyn io::Read>> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(source)),
+            Self::Zstd => Box::new(
+                zstd::stream::read::Decoder::with_buffer(source)
+                    .context("failed to initialize zstd decoder")?,
+            ),
+            Self::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(source)),
+        })
+    }
+}
+
+/// Open a file, transparently decompressing it if it is compressed. Compression is detected by
+/// the file's magic bytes, falling back to its extension.
+pub fn open_file(path: &PathBuf) -> Result<Box<dyn io::Read>> {
+    let path = expand_path(path)?;
+    let mut reader = BufReader::new(File::open(&path).context("failed to open file")?);
+    let compression = Compression::from_magic_bytes(
+        reader.fill_buf().context("failed to read file")?,
+    )
+    .or_else(|| {
+        Path::new(&path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Compression::from_extension)
+    });
+    match compression {
+        Some(compression) => compression
+            .decoder(reader)
+            .with_context(|| format!("failed to decompress file: {path}")),
+        None => Ok(Box::new(reader)),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinSource {
+    /// Applications installed on the system (read from freedesktop `.desktop` entries)
+    DesktopEntries,
+}
+
+impl BuiltinSource {
+    pub async fn read(self) -> Result<Value> {
+        match self {
+            Self::DesktopEntries => {
+                // Reading the desktop entries walks the XDG application directories
+                let entries = tokio::task::spawn_blocking(desktop_entries)
+                    .await
+                    .context("failed to read desktop entries")?;
+                serde_json::to_value(entries).context("failed to serialize desktop entries")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DataSourceKind {
     StdIn,
+    /// Input that was already read (e.g. the STDIN of a `polymenu show` client)
+    Bytes(Arc<[u8]>),
     File(PathBuf),
     Command(Command),
+    Builtin(BuiltinSource),
 }
 
 #[derive(Debug)]
@@ -51,6 +239,11 @@ pub struct DataParser {
     kind: DataSourceKind,
     format: IOFormat,
     headers: Option<Vec<String>>,
+    skip_invalid: bool,
+    all_tables: bool,
+    encoding: Encoding,
+    schema: Option<Arc<Schema>>,
+    transform: Option<Arc<Transform>>,
 }
 
 impl DataParser {
@@ -59,63 +252,129 @@ impl DataParser {
             kind,
             format,
             headers,
+            skip_invalid: false,
+            all_tables: false,
+            encoding: Encoding::default(),
+            schema: None,
+            transform: None,
         }
     }
 
+    /// Apply `transform` to the parsed data (it is compiled once, when the app is built)
+    pub fn with_transform(mut self, transform: Option<Arc<Transform>>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Validate records against `schema` (which is compiled once, when the app is built)
+    pub fn with_schema(mut self, schema: Option<Arc<Schema>>) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Read from `stdin` instead of this process's STDIN
+    pub fn with_stdin(mut self, stdin: Option<Arc<[u8]>>) -> Self {
+        if let (DataSourceKind::StdIn, Some(stdin)) = (&self.kind, stdin) {
+            self.kind = DataSourceKind::Bytes(stdin);
+        }
+        self
+    }
+
     pub async fn parse(
         &self,
         args: Option<&HashMap<String, String>>,
         stdin_lines: Option<Vec<String>>,
     ) -> Result<Value> {
         let mut source: Box<dyn io::Read> = match self.kind.clone() {
-            DataSourceKind::StdIn => Box::new(Cursor::new(STDIN_CONTENT.as_bytes())),
-            DataSourceKind::File(path) => {
-                Box::new(File::open(expand_path(&path)?).context("failed to open file")?)
-            }
+            DataSourceKind::StdIn => Box::new(Cursor::new(
+                STDIN_CONTENT
+                    .as_ref()
+                    .map_err(|e| anyhow!("failed to read STDIN: {e}"))?,
+            )),
+            DataSourceKind::Bytes(bytes) => Box::new(Cursor::new(bytes)),
+            DataSourceKind::File(path) => open_file(&path)?,
             DataSourceKind::Command(callback) => Box::new(
                 callback
                     .call(args, stdin_lines)
                     .await
                     .context("failed to execute callback")?,
             ),
-        };
-        match self.format {
-            IOFormat::HeadlessCsv => read_csv(source, false, self.headers.clone()),
-            IOFormat::Csv => read_csv(source, true, self.headers.clone()),
-            IOFormat::Json => read_json(source),
-            IOFormat::JsonLines => read_jsonlines(source),
-            IOFormat::Raw => {
-                let mut buf = String::new();
-                source
-                    .read_to_string(&mut buf)
-                    .context("failed to read raw input")?;
-                Ok(Value::String(buf))
+            // Built-in sources are already structured, so they don't need to be parsed
+            DataSourceKind::Builtin(builtin) => {
+                return self.finish(builtin.read().await?, Vec::new());
             }
+        };
+        if self.encoding == Encoding::Base64 && self.format != IOFormat::Raw {
+            bail!("the `base64` encoding can only be used with the `raw` format");
+        }
+        // Read the input as bytes, so that it can be decoded according to `encoding`
+        let mut bytes = Vec::new();
+        source
+            .read_to_end(&mut bytes)
+            .context("failed to read input")?;
+        let text = self.encoding.decode(bytes)?;
+        let (data, errors) = match self.format {
+            IOFormat::HeadlessCsv => (
+                read_csv(text.as_bytes(), false, self.headers.clone())?,
+                Vec::new(),
+            ),
+            IOFormat::Csv => (
+                read_csv(text.as_bytes(), true, self.headers.clone())?,
+                Vec::new(),
+            ),
+            IOFormat::Json => (read_json(text.as_bytes())?, Vec::new()),
+            IOFormat::JsonLines => read_jsonlines(text.as_bytes(), self.skip_invalid)?,
+            IOFormat::Raw => (Value::String(text), Vec::new()),
+            IOFormat::MarkdownTable => (
+                read_table(text.as_bytes(), TableSyntax::Markdown, self.all_tables)?,
+                Vec::new(),
+            ),
+            IOFormat::OrgTable => (
+                read_table(text.as_bytes(), TableSyntax::Org, self.all_tables)?,
+                Vec::new(),
+            ),
+        };
+        self.finish(data, errors)
+    }
+
+    /// Apply the `transform` and `schema` to parsed data
+    fn finish(&self, mut data: Value, mut errors: Vec<Value>) -> Result<Value> {
+        if let Some(transform) = &self.transform {
+            data = transform.apply(data)?;
+        }
+        if let Some(schema) = &self.schema {
+            errors.extend(schema.apply(&mut data));
+        }
+        // Report any recoverable problems alongside the data, so the app can warn about them
+        if self.skip_invalid || self.schema.is_some() {
+            Ok(json!({ "data": data, "errors": errors }))
+        } else {
+            Ok(data)
         }
     }
 }
 
-impl From<Config> for DataParser {
-    fn from(value: Config) -> Self {
+impl From<InputConfig> for DataParser {
+    fn from(value: InputConfig) -> Self {
         let format = value.format.unwrap_or_else(|| {
-            if let Some(extension) = value
+            value
                 .file
                 .as_deref()
-                .and_then(|p| p.extension())
-                .and_then(|e| e.to_str())
-            {
-                match extension {
-                    "csv" => IOFormat::Csv,
-                    "json" => IOFormat::Json,
-                    "jsonl" => IOFormat::JsonLines,
-                    _ => IOFormat::Raw,
-                }
-            } else {
-                IOFormat::HeadlessCsv
-            }
+                .map(IOFormat::from_path)
+                .unwrap_or(IOFormat::HeadlessCsv)
         });
         let kind = if let Some(path) = &value.file {
             DataSourceKind::File(path.to_path_buf())
+        } else if let Some(command) = value.command {
+            DataSourceKind::Command(Command {
+                command,
+                output_format: format,
+                encoding: value.encoding,
+                transform: None,
+                unwrap_transform: false,
+            })
+        } else if let Some(builtin) = value.builtin {
+            DataSourceKind::Builtin(builtin)
         } else {
             DataSourceKind::StdIn
         };
@@ -123,10 +382,29 @@ impl From<Config> for DataParser {
             kind,
             format,
             headers: value.headers,
+            skip_invalid: value.skip_invalid,
+            all_tables: value.all_tables,
+            encoding: value.encoding,
+            // The schema and transform are compiled once, when the app is built (see
+            // `with_schema` and `with_transform`)
+            schema: None,
+            transform: None,
         }
     }
 }
 
+impl From<Command> for DataParser {
+    fn from(value: Command) -> Self {
+        let mut parser = Self::new(
+            DataSourceKind::Command(value.clone()),
+            value.output_format,
+            None,
+        );
+        parser.encoding = value.encoding;
+        parser
+    }
+}
+
 pub fn read_csv(
     source: impl io::Read,
     has_headers: bool,
@@ -154,19 +432,495 @@ pub fn read_csv(
     }
 }
 
-pub fn read_jsonlines(source: impl io::Read) -> Result<Value> {
-    let objects: Vec<Value> = BufReader::new(source)
-        .lines()
-        .map(|line| {
-            line.context("failed to read line").and_then(|l| {
-                serde_json::from_str::<Value>(&l)
-                    .with_context(|| format!("failed to parse json from string:\n\"{}\"", &l))
-            })
-        })
-        .collect::<Result<_>>()?;
-    Ok(Value::Array(objects))
+/// Read JSON lines, ignoring blank lines. If `skip_invalid` is set, lines that cannot be parsed
+/// are skipped, and an error is returned alongside the data for each of them with the (1-based)
+/// `line` and `column` numbers and an `error` message.
+pub fn read_jsonlines(source: impl io::Read, skip_invalid: bool) -> Result<(Value, Vec<Value>)> {
+    let mut objects = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in BufReader::new(source).split(b'\n').enumerate() {
+        let line = line.context("failed to read line")?;
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        match serde_json::from_slice::<Value>(&line) {
+            Ok(object) => objects.push(object),
+            Err(e) if skip_invalid => {
+                // The error's own position is relative to the line, so report it separately
+                let position = format!(" at line {} column {}", e.line(), e.column());
+                let message = e.to_string();
+                errors.push(json!({
+                    "line": i + 1,
+                    "column": e.column(),
+                    "error": message.strip_suffix(&position).unwrap_or(&message),
+                }));
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "failed to parse json from line {}:\n\"{}\"",
+                        i + 1,
+                        String::from_utf8_lossy(&line)
+                    )
+                });
+            }
+        }
+    }
+    Ok((Value::Array(objects), errors))
 }
 
 pub fn read_json(source: impl io::Read) -> Result<Value> {
     serde_json::from_reader(source).context("failed to parse json")
 }
+
+/// The syntax of a plain-text table
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TableSyntax {
+    Markdown,
+    Org,
+}
+
+impl TableSyntax {
+    /// The text of a heading, if `line` is one
+    fn heading(self, line: &str) -> Option<&str> {
+        let marker = match self {
+            Self::Markdown => '#',
+            Self::Org => '*',
+        };
+        let text = line.trim_start_matches(marker);
+        if text.len() == line.len() || !(text.is_empty() || text.starts_with(' ')) {
+            return None;
+        }
+        let text = text.trim();
+        Some(match self {
+            // Markdown headings may be closed by a sequence of `#`s
+            Self::Markdown => text.trim_end_matches('#').trim_end(),
+            // Org headings may end with tags (e.g. `:work:urgent:`)
+            Self::Org => match text.rsplit_once(' ') {
+                Some((title, tags))
+                    if tags.len() > 1 && tags.starts_with(':') && tags.ends_with(':') =>
+                {
+                    title.trim_end()
+                }
+                _ => text,
+            },
+        })
+    }
+
+    /// Whether `line` starts or ends a block of code (which may contain things that look like
+    /// tables, but aren't)
+    fn is_code_fence(self, line: &str) -> bool {
+        match self {
+            Self::Markdown => line.starts_with("```") || line.starts_with("~~~"),
+            Self::Org => {
+                let line = line.to_ascii_lowercase();
+                line.starts_with("#+begin_") || line.starts_with("#+end_")
+            }
+        }
+    }
+}
+
+/// Read the tables in a Markdown or Org document, converting each row to a JSON object using the
+/// table's header row. Org tables don't need a header: the rows of a table without one are read
+/// as arrays of cells. If `all_tables` is set, the result is an object with the rows of every
+/// table, keyed by the heading that precedes it. Otherwise, only the first table is read.
+pub fn read_table(source: impl io::Read, syntax: TableSyntax, all_tables: bool) -> Result<Value> {
+    let text = io::read_to_string(source).context("failed to read table")?;
+    let mut lines = text.lines().map(str::trim).peekable();
+    let mut tables = serde_json::Map::new();
+    let mut heading = String::new();
+    let mut in_code_block = false;
+    while let Some(line) = lines.next() {
+        if syntax.is_code_fence(line) {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            continue;
+        }
+        if let Some(text) = syntax.heading(line) {
+            heading = text.to_string();
+            continue;
+        }
+        // Org tables can start with a rule above the header
+        if is_delimiter_row(line) {
+            continue;
+        }
+        // A table starts with a header row, followed by a delimiter row (which Org tables can
+        // leave out, in which case they have no header)
+        let header = if is_table_row(line)
+            && lines
+                .peek()
+                .is_some_and(|l| is_table_row(l) && is_delimiter_row(l))
+        {
+            lines.next();
+            Some(split_row(line))
+        } else if syntax == TableSyntax::Org && line.starts_with('|') {
+            None
+        } else {
+            continue;
+        };
+        let mut rows = Vec::new();
+        if header.is_none() {
+            rows.push(Value::Array(
+                split_row(line).into_iter().map(Value::String).collect(),
+            ));
+        }
+        while let Some(line) = lines.next_if(|l| is_table_row(l)) {
+            if is_delimiter_row(line) {
+                continue;
+            }
+            let cells = split_row(line);
+            let row = match &header {
+                Some(header) => Value::Object(
+                    header
+                        .iter()
+                        .enumerate()
+                        .map(|(i, key)| {
+                            let cell = cells.get(i).cloned().unwrap_or_default();
+                            (key.clone(), Value::String(cell))
+                        })
+                        .collect(),
+                ),
+                None => Value::Array(cells.into_iter().map(Value::String).collect()),
+            };
+            rows.push(row);
+        }
+        if !all_tables {
+            return Ok(Value::Array(rows));
+        }
+        let mut key = heading.clone();
+        let mut count = 1;
+        while tables.contains_key(&key) {
+            count += 1;
+            key = format!("{heading} ({count})");
+        }
+        tables.insert(key, Value::Array(rows));
+    }
+    if all_tables {
+        Ok(Value::Object(tables))
+    } else {
+        bail!("no table found in input")
+    }
+}
+
+/// Whether `line` can be a table row: it has to start or end with a pipe, so that lines that
+/// merely contain one (e.g. a paragraph followed by a `---` heading underline) aren't mistaken
+/// for tables
+fn is_table_row(line: &str) -> bool {
+    line.starts_with('|') || line.ends_with('|')
+}
+
+/// Whether `line` separates a table's header from its body (e.g. `|---|:--:|` or `|---+---|`)
+fn is_delimiter_row(line: &str) -> bool {
+    line.contains('-')
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | '+' | ' ' | '\t'))
+}
+
+/// Split a table row into its (trimmed) cells, treating `\|` as an escaped pipe
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line,
+    };
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next_if_eq(&'|').is_some() => cell.push('|'),
+            '|' => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SchemaSource;
+
+    fn table(src: &str, syntax: TableSyntax) -> Value {
+        read_table(src.as_bytes(), syntax, false).unwrap()
+    }
+
+    #[test]
+    fn split_row_handles_escaped_pipes() {
+        assert_eq!(split_row("| a | b\\|c | d |"), ["a", "b|c", "d"]);
+        assert_eq!(split_row("a | b"), ["a", "b"]);
+        assert_eq!(split_row("| a | b\\|"), ["a", "b|"]);
+        assert_eq!(split_row("| a || c |"), ["a", "", "c"]);
+    }
+
+    #[test]
+    fn delimiter_rows() {
+        assert!(is_delimiter_row("|---|:--:|--:|"));
+        assert!(is_delimiter_row("|---+---|"));
+        assert!(is_delimiter_row(":--- | ---"));
+        assert!(!is_delimiter_row("| a | b |"));
+        assert!(!is_delimiter_row("| | |"));
+    }
+
+    #[test]
+    fn markdown_tables() {
+        let src = "\
+# Title
+
+| name | value |
+|:-----|------:|
+| a    | 1     |
+| b \\| c |    |
+| d |
+";
+        assert_eq!(
+            table(src, TableSyntax::Markdown),
+            json!([
+                { "name": "a", "value": "1" },
+                { "name": "b | c", "value": "" },
+                { "name": "d", "value": "" },
+            ])
+        );
+    }
+
+    #[test]
+    fn rows_need_a_leading_or_trailing_pipe() {
+        let src = "\
+a | b
+---
+
+| name | value
+|------|------
+| a    | 1
+c | d
+";
+        assert_eq!(
+            table(src, TableSyntax::Markdown),
+            json!([{ "name": "a", "value": "1" }])
+        );
+        // A heading underlined with `---` isn't a table
+        assert!(read_table("a | b\n---\n".as_bytes(), TableSyntax::Markdown, false).is_err());
+    }
+
+    #[test]
+    fn tables_in_code_blocks_are_ignored() {
+        let src = "\
+```
+| a |
+|---|
+| 1 |
+```
+| b |
+|---|
+| 2 |
+";
+        assert_eq!(table(src, TableSyntax::Markdown), json!([{ "b": "2" }]));
+    }
+
+    #[test]
+    fn org_tables() {
+        let src = "\
+* Heading
+|---+---|
+| a | b |
+|---+---|
+| 1 | 2 |
+|---+---|
+| 3 |
+";
+        assert_eq!(
+            table(src, TableSyntax::Org),
+            json!([{ "a": "1", "b": "2" }, { "a": "3", "b": "" }])
+        );
+    }
+
+    #[test]
+    fn org_tables_without_a_header() {
+        let src = "| 1 | 2 |\n| 3 | 4 |\n";
+        assert_eq!(
+            table(src, TableSyntax::Org),
+            json!([["1", "2"], ["3", "4"]])
+        );
+        // Markdown tables always need a header
+        assert!(read_table(src.as_bytes(), TableSyntax::Markdown, false).is_err());
+    }
+
+    #[test]
+    fn all_tables_are_keyed_by_heading() {
+        let src = "\
+## First
+| a |
+|---|
+| 1 |
+
+## First
+| b |
+|---|
+| 2 |
+";
+        assert_eq!(
+            read_table(src.as_bytes(), TableSyntax::Markdown, true).unwrap(),
+            json!({ "First": [{ "a": "1" }], "First (2)": [{ "b": "2" }] })
+        );
+    }
+
+    #[test]
+    fn json_lines_skip_blank_lines() {
+        let src = "{\"a\": 1}\r\n\n  \n[2]\n\"three\"";
+        let (data, errors) = read_jsonlines(src.as_bytes(), false).unwrap();
+        assert_eq!(data, json!([{ "a": 1 }, [2], "three"]));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_json_lines_fail_unless_skipped() {
+        let src = "{\"a\": 1}\n\n{\"a\": }\n{\"a\": 3}\n";
+        let error = read_jsonlines(src.as_bytes(), false).unwrap_err();
+        assert!(format!("{error:#}").contains("line 3"));
+
+        let (data, errors) = read_jsonlines(src.as_bytes(), true).unwrap();
+        assert_eq!(data, json!([{ "a": 1 }, { "a": 3 }]));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["line"], 3);
+        assert_eq!(errors[0]["column"], 7);
+        let message = errors[0]["error"].as_str().unwrap();
+        assert!(!message.contains("at line"), "{message}");
+    }
+
+    #[test]
+    fn utf16_byte_order() {
+        let big_endian = [0xfe, 0xff, 0x00, b'h', 0x00, b'i'];
+        let little_endian = [0xff, 0xfe, b'h', 0x00, b'i', 0x00];
+        assert_eq!(decode_utf16(&big_endian).unwrap(), "hi");
+        assert_eq!(decode_utf16(&little_endian).unwrap(), "hi");
+        // Without a BOM, UTF-16 is assumed to be little-endian
+        assert_eq!(decode_utf16(&little_endian[2..]).unwrap(), "hi");
+        // Surrogate pairs
+        assert_eq!(
+            decode_utf16(&[0x3d, 0xd8, 0x00, 0xde]).unwrap(),
+            "\u{1f600}"
+        );
+    }
+
+    #[test]
+    fn invalid_utf16() {
+        assert!(decode_utf16(&[b'h', 0x00, b'i']).is_err());
+        // An unpaired surrogate
+        assert!(decode_utf16(&[0x3d, 0xd8, b'h', 0x00]).is_err());
+    }
+
+    #[test]
+    fn decode_encodings() {
+        let bytes = b"\xef\xbb\xbfcaf\xc3\xa9".to_vec();
+        assert_eq!(Encoding::Utf8.decode(bytes).unwrap(), "caf\u{e9}");
+        assert!(Encoding::Utf8.decode(b"caf\xe9".to_vec()).is_err());
+        assert_eq!(
+            Encoding::Latin1.decode(b"caf\xe9".to_vec()).unwrap(),
+            "caf\u{e9}"
+        );
+        assert_eq!(
+            Encoding::Lossy.decode(b"caf\xe9".to_vec()).unwrap(),
+            "caf\u{fffd}"
+        );
+        assert_eq!(
+            Encoding::Base64
+                .decode(b"\xff\xd8\x00abc".to_vec())
+                .unwrap(),
+            "/9gAYWJj"
+        );
+    }
+
+    #[tokio::test]
+    async fn base64_is_only_for_raw_input() {
+        let bytes: Arc<[u8]> = Arc::from(&b"\xff\x00"[..]);
+        let parser = |format| {
+            let mut parser = DataParser::new(DataSourceKind::Bytes(bytes.clone()), format, None);
+            parser.encoding = Encoding::Base64;
+            parser
+        };
+        assert_eq!(
+            parser(IOFormat::Raw).parse(None, None).await.unwrap(),
+            json!("/wA=")
+        );
+        assert!(parser(IOFormat::Json).parse(None, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn schema_errors_are_reported_with_the_data() {
+        let bytes: Arc<[u8]> = Arc::from(&b"{\"name\": \"a\"}\n{\"name\": 1}\nnot json\n"[..]);
+        let schema =
+            SchemaSource::Inline(json!({ "properties": { "name": { "type": "string" } } }))
+                .load()
+                .unwrap();
+        let mut parser = DataParser::new(DataSourceKind::Bytes(bytes), IOFormat::JsonLines, None)
+            .with_schema(Some(Arc::new(schema)));
+        parser.skip_invalid = true;
+        let result = parser.parse(None, None).await.unwrap();
+        assert_eq!(result["data"], json!([{ "name": "a" }]));
+        assert_eq!(result["errors"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn compression_from_magic_bytes() {
+        assert_eq!(
+            Compression::from_magic_bytes(&[0x1f, 0x8b, 0x08]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::from_magic_bytes(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Some(Compression::Xz)
+        );
+        assert_eq!(Compression::from_magic_bytes(b"{\"a\": 1}"), None);
+        // Too short to tell
+        assert_eq!(Compression::from_magic_bytes(&[0x1f]), None);
+        assert_eq!(Compression::from_magic_bytes(&[]), None);
+    }
+
+    #[test]
+    fn compressed_files_are_decompressed() {
+        use std::io::Write;
+
+        let data = b"{\"a\": 1}\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(data).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(data).unwrap();
+        let files = [
+            ("gz", gzip.finish().unwrap()),
+            ("zst", zstd::encode_all(&data[..], 0).unwrap()),
+            ("xz", xz.finish().unwrap()),
+        ];
+        for (extension, compressed) in files {
+            // Compression is detected from the contents, so the extension doesn't matter
+            let path = std::env::temp_dir().join(format!(
+                "polymenu-io-test-{}.{extension}.json",
+                std::process::id()
+            ));
+            std::fs::write(&path, compressed).unwrap();
+            let mut contents = Vec::new();
+            let result = open_file(&path).and_then(|mut file| Ok(file.read_to_end(&mut contents)?));
+            std::fs::remove_file(&path).unwrap();
+            result.unwrap();
+            assert_eq!(contents, data, "{extension}");
+        }
+    }
+
+    #[test]
+    fn format_from_path() {
+        let format = |path: &str| IOFormat::from_path(Path::new(path));
+        assert_eq!(format("a.json"), IOFormat::Json);
+        assert_eq!(format("a.jsonl.gz"), IOFormat::JsonLines);
+        // Documents aren't necessarily tables, so they need an explicit format
+        assert_eq!(format("notes.md"), IOFormat::Raw);
+        assert_eq!(format("notes.org.zst"), IOFormat::Raw);
+        assert_eq!(format("a.txt"), IOFormat::Raw);
+    }
+}
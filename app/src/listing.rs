@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result, bail};
+use globset::Glob;
+use ignore::WalkBuilder;
+use serde::Serialize;
+
+use crate::files::Mount;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A file or directory in a mount
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    /// The path of the entry relative to the mount
+    pub path: PathBuf,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub entry_type: EntryType,
+    /// Size in bytes (files only)
+    pub size: Option<u64>,
+    /// Last modification time in seconds since the Unix epoch
+    pub mtime: Option<u64>,
+    pub mime_type: Option<String>,
+}
+
+/// List the entries of the directory at `path` within a mount, skipping anything the mount doesn't
+/// allow access to and anything excluded by `.gitignore` or `.ignore` files. If `glob` is
+/// provided, only entries whose path (relative to the listed directory) matches it are returned.
+/// Entries that can't be read (e.g. because of permissions, or because they were deleted while
+/// listing) are skipped.
+pub fn list(
+    mount: Arc<Mount>,
+    path: &Path,
+    recursive: bool,
+    glob: Option<&str>,
+) -> Result<Vec<Entry>> {
+    if !mount.allows(path) {
+        bail!("path is outside of the mount or not accessible: {path:?}");
+    }
+    let root = &mount.root;
+    let dir = root.join(path);
+    if !dir.is_dir() {
+        bail!("not a directory: {path:?}");
+    }
+    let matcher = glob
+        .map(|glob| Glob::new(glob).map(|g| g.compile_matcher()))
+        .transpose()
+        .with_context(|| format!("invalid glob: {}", glob.unwrap_or_default()))?;
+
+    let mut entries = Vec::new();
+    let walker = WalkBuilder::new(&dir)
+        .max_depth((!recursive).then_some(1))
+        // Respect `.gitignore` files even if the mount isn't a git repository
+        .require_git(false)
+        .hidden(!mount.hidden)
+        .follow_links(mount.follow_symlinks)
+        .filter_entry({
+            let mount = mount.clone();
+            move |entry| {
+                // A followed symlink can point outside of the mount, which would leak the names
+                // of the files there
+                let inside_mount = || {
+                    std::fs::canonicalize(entry.path())
+                        .is_ok_and(|path| path.starts_with(&mount.root))
+                };
+                entry
+                    .path()
+                    .strip_prefix(&mount.root)
+                    .is_ok_and(|relative| mount.allows(relative))
+                    && (!mount.follow_symlinks || inside_mount())
+            }
+        })
+        .build();
+    let mut skipped = 0;
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => {
+                skipped += 1;
+                continue;
+            }
+        };
+        if entry.depth() == 0 {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&dir)?;
+        if let Some(matcher) = &matcher
+            && !matcher.is_match(relative)
+        {
+            continue;
+        }
+        match to_entry(root, entry.path()) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        tracing::warn!("skipped {skipped} unreadable entries while listing {dir:?}");
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+fn to_entry(root: &Path, path: &Path) -> Result<Entry> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("could not read metadata of: {path:?}"))?;
+    let file_type = metadata.file_type();
+    let entry_type = if file_type.is_symlink() {
+        EntryType::Symlink
+    } else if file_type.is_dir() {
+        EntryType::Directory
+    } else {
+        EntryType::File
+    };
+    let mime_type = match entry_type {
+        EntryType::Directory => Some("inode/directory".to_string()),
+        EntryType::Symlink => Some("inode/symlink".to_string()),
+        EntryType::File => mime_guess::from_path(path).first().map(|m| m.to_string()),
+    };
+    Ok(Entry {
+        path: path.strip_prefix(root)?.to_path_buf(),
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        entry_type,
+        size: file_type.is_file().then_some(metadata.len()),
+        mtime: metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        mime_type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MountConfig;
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_symlinks_stay_inside_the_mount() {
+        let dir =
+            std::env::temp_dir().join(format!("polymenu-listing-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for file in ["mount/dir/a.txt", "outside/secret.txt"] {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let root = dir.join("mount");
+        std::os::unix::fs::symlink(root.join("dir"), root.join("inside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), root.join("dangling")).unwrap();
+        let config = MountConfig {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let mount = Arc::new(Mount::new(&root, &config).unwrap());
+        let entries = list(mount, Path::new(""), true, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let paths: Vec<_> = entries.unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(
+            paths,
+            ["dir", "dir/a.txt", "inside", "inside/a.txt"].map(PathBuf::from)
+        );
+    }
+}
@@ -1,38 +1,59 @@
-use self::config::{Config, UpdateFromOther};
+use self::auth::Auth;
+use self::config::{Cli, Config, Mode};
 use self::develop::{ping_dev_server, run_command, run_dev_server};
 use self::gui::{AppEvent, run_gui};
+use self::server::{Listener, OnClose};
 use anyhow::{Context, Result, anyhow};
 use clap::Parser;
 use tao::event_loop::{EventLoop, EventLoopBuilder};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
+mod auth;
 mod command;
 mod config;
+#[cfg(unix)]
+mod daemon;
+mod desktop;
 mod develop;
+mod events;
 mod expansion;
+mod files;
 mod gui;
+mod icons;
 mod io;
 mod keybinds;
+mod listing;
+mod protocol;
+#[cfg(unix)]
+mod resident;
+mod schema;
+mod security;
 mod server;
+mod thumbnail;
+mod transform;
+mod watch;
+mod xdg;
 
 fn main() -> Result<()> {
-    let cli_opts = Config::try_parse()?;
-    let mut config = Config::from_file(
-        &cli_opts
-            .config
-            .to_owned()
-            .unwrap_or_else(Config::default_path),
-    )?;
-    config.update_from_other(cli_opts);
-    config = config.apply_cli_overrides()?;
-
+    let cli = Cli::try_parse()?;
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(1)
         .build()
         .context("failed to initialize tokio")?;
 
+    let cli_opts = match cli.mode {
+        #[cfg(unix)]
+        Some(Mode::Daemon) => return daemon::run(&rt),
+        #[cfg(unix)]
+        Some(Mode::Show(cli_opts)) => return rt.block_on(daemon::show(load_config(*cli_opts)?)),
+        #[cfg(not(unix))]
+        Some(_) => return Err(anyhow!("daemon mode is only supported on Unix")),
+        None => cli.config,
+    };
+    let mut config = load_config(cli_opts)?;
+
     if config.compile {
         let src = config.src.as_ref().expect("no `src` provided");
         if let Some(install_cmd) = config.install_command {
@@ -47,14 +68,66 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    #[cfg(unix)]
+    let resident_socket = config.resident.then(|| resident::socket_path(&config));
+    #[cfg(unix)]
+    if let Some(path) = &resident_socket
+        && rt.block_on(resident::reopen(path))
+    {
+        // The app is already running in the background, and has been asked to show its window
+        return Ok(());
+    }
+    #[cfg(not(unix))]
+    if config.resident {
+        return Err(anyhow!("resident apps are only supported on Unix"));
+    }
+
     let event_loop: EventLoop<AppEvent> = EventLoopBuilder::with_user_event().build();
     let event_loop_proxy = event_loop.create_proxy();
     let shutdown_token = CancellationToken::new();
+    let on_close = if config.resident {
+        let (hide_sender, mut hide_requests) = tokio::sync::mpsc::unbounded_channel();
+        let event_loop_proxy = event_loop_proxy.clone();
+        rt.spawn(async move {
+            while hide_requests.recv().await.is_some() {
+                let _ = event_loop_proxy.send_event(AppEvent::Hide);
+            }
+        });
+        OnClose::Hide(hide_sender)
+    } else {
+        OnClose::Shutdown
+    };
+    #[cfg(unix)]
+    if let Some(path) = resident_socket {
+        let event_loop_proxy = event_loop_proxy.clone();
+        let shutdown_token = shutdown_token.clone();
+        rt.spawn(async move {
+            if let Err(e) = resident::listen(&path, event_loop_proxy, shutdown_token).await {
+                eprintln!("{e:#}");
+            }
+        });
+    }
+    let (app_sender, app_receiver) = tokio::sync::watch::channel(None);
+    let listener = if config.in_process && !config.develop {
+        Listener::InProcess(app_sender)
+    } else if config.socket && !config.develop {
+        // Bind before starting the GUI, which loads the app through the socket right away
+        rt.block_on(server::bind_private_socket(&config))?
+    } else {
+        // Bind before starting anything else, so that the GUI (and dev server) can be pointed at
+        // the port that was actually bound
+        let listener = rt.block_on(server::bind(&config))?;
+        config.port = listener.local_addr()?.port().to_string();
+        Listener::Tcp(listener)
+    };
+    // The window gets its own token, unless its requests don't need to be authenticated
+    let auth = Auth::default();
+    let window_token = (!matches!(listener, Listener::InProcess(_))).then(|| auth.open_window());
     let server: JoinHandle<Result<()>> = {
         let server_config = config.clone();
         let shutdown_token = shutdown_token.clone();
         rt.spawn(async move {
-            server::run(server_config, shutdown_token)
+            server::run(server_config, listener, auth, on_close, shutdown_token)
                 .await
                 .context("problem with server")
         })
@@ -93,9 +166,27 @@ fn main() -> Result<()> {
         let _ = event_loop_proxy.send_event(AppEvent::Shutdown);
     });
 
-    let gui_result = run_gui(&config, event_loop, shutdown_token.clone());
+    let gui_result = run_gui(
+        &config,
+        window_token,
+        event_loop,
+        shutdown_token.clone(),
+        rt.handle(),
+        app_receiver,
+    );
     if gui_result.is_err() {
         shutdown_token.cancel();
     }
     gui_result
 }
+
+/// Load the config file, and apply the CLI options on top of it
+fn load_config(cli_opts: Config) -> Result<Config> {
+    let config = Config::from_file(
+        &cli_opts
+            .config
+            .to_owned()
+            .unwrap_or_else(Config::default_path),
+    )?;
+    config.with_cli_options(cli_opts)
+}
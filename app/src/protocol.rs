@@ -0,0 +1,203 @@
+use std::borrow::Cow;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+#[cfg(unix)]
+use axum::body::Bytes;
+use axum::{Router, body::Body};
+use http_body_util::BodyExt;
+#[cfg(unix)]
+use http_body_util::Full;
+#[cfg(unix)]
+use hyper_util::rt::TokioIo;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tower::ServiceExt;
+#[cfg(unix)]
+use wry::http::HeaderValue;
+use wry::http::{Request, Response, StatusCode, header};
+use wry::{RequestAsyncResponder, WebViewId};
+
+/// The custom protocol that the webview uses to reach the server when it isn't listening on a TCP
+/// port
+pub const SCHEME: &str = "polymenu";
+
+/// The URL of the app's root when it is loaded through the custom protocol
+pub fn url() -> String {
+    // Windows and Android don't support custom protocols, so wry serves them from a special
+    // `http` origin instead
+    if cfg!(any(target_os = "windows", target_os = "android")) {
+        format!("http://{SCHEME}.localhost/")
+    } else {
+        format!("{SCHEME}://localhost/")
+    }
+}
+
+/// Handle requests to the `polymenu://` protocol by calling the app's router directly. The router
+/// is sent by the server once it has started (requests made before then wait for it).
+pub fn serve_in_process(
+    app: watch::Receiver<Option<Router>>,
+    runtime: Handle,
+) -> impl Fn(WebViewId, Request<Vec<u8>>, RequestAsyncResponder) + 'static {
+    move |_, request, responder| {
+        let mut app = app.clone();
+        runtime.spawn(async move {
+            let response = call(&mut app, request).await.unwrap_or_else(bad_gateway);
+            responder.respond(response);
+        });
+    }
+}
+
+async fn call(
+    app: &mut watch::Receiver<Option<Router>>,
+    request: Request<Vec<u8>>,
+) -> Result<Response<Cow<'static, [u8]>>> {
+    let app = app
+        .wait_for(Option::is_some)
+        .await
+        .map_err(|_| anyhow!("server is not running"))?
+        .clone()
+        .expect("router was sent");
+    let response = app
+        .oneshot(request.map(Body::from))
+        .await
+        .context("request to server failed")?;
+    collect_response(response).await
+}
+
+/// Handle requests to the `polymenu://` protocol by forwarding them to the server's Unix socket.
+/// Only the webview can make these requests, so they are authenticated on its behalf (with its
+/// window's token).
+#[cfg(unix)]
+pub fn forward_to_socket(
+    socket_path: PathBuf,
+    token: Option<String>,
+    runtime: Handle,
+) -> impl Fn(WebViewId, Request<Vec<u8>>, RequestAsyncResponder) + 'static {
+    move |_, request, responder| {
+        let socket_path = socket_path.clone();
+        let token = token.clone();
+        runtime.spawn(async move {
+            let response = forward(&socket_path, token.as_deref(), request)
+                .await
+                .unwrap_or_else(bad_gateway);
+            responder.respond(response);
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn forward(
+    socket_path: &Path,
+    token: Option<&str>,
+    request: Request<Vec<u8>>,
+) -> Result<Response<Cow<'static, [u8]>>> {
+    let stream = UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!("could not connect to server socket: {socket_path:?}"))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .context("could not connect to server")?;
+    tokio::spawn(connection);
+
+    let (mut parts, body) = request.into_parts();
+    // The server only needs the path, since it's already known which server the request is for
+    parts.uri = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/")
+        .parse()?;
+    parts
+        .headers
+        .insert(header::HOST, HeaderValue::from_static("localhost"));
+    if let Some(token) = token {
+        parts.headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}"))?,
+        );
+    }
+    let response = sender
+        .send_request(Request::from_parts(parts, Full::new(Bytes::from(body))))
+        .await
+        .context("request to server failed")?;
+    collect_response(response).await
+}
+
+/// Read a response's whole body, since the webview can't receive it in chunks. Streaming
+/// responses (e.g. server-sent events) never end, so they are rejected instead of waiting for them
+/// forever.
+async fn collect_response<B>(response: Response<B>) -> Result<Response<Cow<'static, [u8]>>>
+where
+    B: BodyExt,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    if is_streaming(&response) {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(Cow::Borrowed(
+                "streaming responses can't be delivered through the `polymenu://` protocol"
+                    .as_bytes(),
+            ))
+            .expect("response is valid"));
+    }
+    let (parts, body) = response.into_parts();
+    let body = body
+        .collect()
+        .await
+        .context("could not read response from server")?;
+    Ok(Response::from_parts(
+        parts,
+        Cow::Owned(body.to_bytes().to_vec()),
+    ))
+}
+
+fn is_streaming<B>(response: &Response<B>) -> bool {
+    let event_stream = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .is_some_and(|t| t.starts_with("text/event-stream"));
+    event_stream || response.status() == StatusCode::SWITCHING_PROTOCOLS
+}
+
+fn bad_gateway(e: anyhow::Error) -> Response<Cow<'static, [u8]>> {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Cow::Owned(format!("{e:#}").into_bytes()))
+        .expect("response is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn streaming_responses_are_rejected() {
+        let stream = futures_util::stream::pending::<Result<axum::body::Bytes, std::io::Error>>();
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .body(Body::from_stream(stream))
+            .unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(5), collect_response(response))
+            .await
+            .expect("streaming response was collected")
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[tokio::test]
+    async fn complete_responses_are_collected() {
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("[1, 2]"))
+            .unwrap();
+        let response = collect_response(response).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), b"[1, 2]");
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use md5::{Digest, Md5};
+use tao::event_loop::EventLoopProxy;
+use tokio::net::UnixStream;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::gui::AppEvent;
+use crate::server::bind_socket;
+
+/// The socket that a resident app listens on while it is running (one per config file)
+pub fn socket_path(config: &Config) -> PathBuf {
+    let config_path = config.config.clone().unwrap_or_else(Config::default_path);
+    let config_path = std::fs::canonicalize(&config_path).unwrap_or(config_path);
+    let hash = format!(
+        "{:x}",
+        Md5::digest(config_path.as_os_str().as_encoded_bytes())
+    );
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("polymenu")
+        .join(format!("resident-{hash}.sock"))
+}
+
+/// Ask the running instance of a resident app to show its window. Returns `false` if the app
+/// isn't running.
+pub async fn reopen(path: &Path) -> bool {
+    // Connecting is the request, so nothing needs to be sent
+    UnixStream::connect(path).await.is_ok()
+}
+
+/// Show the window again whenever the app is run again or receives `SIGUSR1`, until the app shuts
+/// down
+pub async fn listen(
+    path: &Path,
+    event_loop_proxy: EventLoopProxy<AppEvent>,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    let listener = bind_socket(path)?;
+    let mut signals =
+        signal(SignalKind::user_defined1()).context("could not listen for SIGUSR1")?;
+    loop {
+        tokio::select! {
+            _ = shutdown_token.cancelled() => break,
+            _ = signals.recv() => {}
+            accepted = listener.accept() => {
+                accepted.context("could not accept connection")?;
+            }
+        }
+        if event_loop_proxy.send_event(AppEvent::Reopen).is_err() {
+            break;
+        }
+    }
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::expansion::expand_path;
+
+/// A JSON Schema describing each record of an input, either written inline in the config or as
+/// a path to a JSON file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SchemaSource {
+    Path(PathBuf),
+    Inline(Value),
+}
+
+impl SchemaSource {
+    /// Load the schema, checking that it is a valid JSON Schema
+    pub fn load(&self) -> Result<Schema> {
+        let schema = match self {
+            Self::Path(path) => {
+                let path = expand_path(path)?;
+                let src = std::fs::read_to_string(&path)
+                    .with_context(|| format!("could not read schema from: {path}"))?;
+                serde_json::from_str(&src)
+                    .with_context(|| format!("could not parse schema from: {path}"))?
+            }
+            Self::Inline(schema) => schema.clone(),
+        };
+        let validator =
+            jsonschema::validator_for(&schema).map_err(|e| anyhow!("invalid schema: {e}"))?;
+        Ok(Schema { schema, validator })
+    }
+}
+
+/// The compiled schemas of an app's inputs, keyed by input name (`None` for the default input)
+pub type Schemas = HashMap<Option<String>, Arc<Schema>>;
+
+#[derive(Debug)]
+pub struct Schema {
+    pub schema: Value,
+    validator: jsonschema::Validator,
+}
+
+impl Schema {
+    /// Apply defaults to, and validate, each record of `data` (each element if `data` is an
+    /// array, or `data` itself otherwise). Invalid records are removed from arrays, and an error
+    /// is returned for each problem found.
+    pub fn apply(&self, data: &mut Value) -> Vec<Value> {
+        let mut errors = Vec::new();
+        match data {
+            Value::Array(records) => {
+                let mut index = 0;
+                records.retain_mut(|record| {
+                    let record_errors = self.apply_to_record(record, Some(index));
+                    index += 1;
+                    let valid = record_errors.is_empty();
+                    errors.extend(record_errors);
+                    valid
+                });
+            }
+            record => errors.extend(self.apply_to_record(record, None)),
+        }
+        errors
+    }
+
+    fn apply_to_record(&self, record: &mut Value, index: Option<usize>) -> Vec<Value> {
+        apply_defaults(&self.schema, record);
+        self.validator
+            .iter_errors(record)
+            .map(|e| {
+                json!({
+                    "record": index,
+                    "path": e.instance_path().to_string(),
+                    "error": e.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Fill in missing properties of `value` that have a `default` in `schema`
+fn apply_defaults(schema: &Value, value: &mut Value) {
+    let (Some(properties), Value::Object(object)) =
+        (schema.get("properties").and_then(Value::as_object), value)
+    else {
+        return;
+    };
+    for (key, property) in properties {
+        match object.get_mut(key) {
+            Some(v) => apply_defaults(property, v),
+            None => {
+                if let Some(default) = property.get("default") {
+                    object.insert(key.clone(), default.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        SchemaSource::Inline(json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": { "type": "string" },
+                "enabled": { "type": "boolean", "default": true },
+                "window": {
+                    "type": "object",
+                    "properties": { "width": { "type": "integer", "default": 800 } }
+                }
+            }
+        }))
+        .load()
+        .unwrap()
+    }
+
+    #[test]
+    fn defaults_are_filled_in() {
+        let mut data = json!([
+            { "name": "a" },
+            { "name": "b", "enabled": false, "window": {} },
+        ]);
+        assert!(schema().apply(&mut data).is_empty());
+        assert_eq!(
+            data,
+            json!([
+                { "name": "a", "enabled": true },
+                { "name": "b", "enabled": false, "window": { "width": 800 } },
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_records_are_removed() {
+        let mut data = json!([
+            { "name": "a" },
+            { "enabled": false },
+            { "name": "c", "window": { "width": "wide" } },
+        ]);
+        let errors = schema().apply(&mut data);
+        assert_eq!(data, json!([{ "name": "a", "enabled": true }]));
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["record"], json!(1));
+        assert_eq!(errors[1]["record"], json!(2));
+        assert_eq!(errors[1]["path"], json!("/window/width"));
+    }
+
+    #[test]
+    fn single_records_are_validated_in_place() {
+        let mut data = json!({ "enabled": "yes" });
+        let errors = schema().apply(&mut data);
+        // A single record is kept, so that its errors can be shown next to it
+        assert_eq!(data, json!({ "enabled": "yes" }));
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e["record"].is_null()));
+    }
+
+    #[test]
+    fn schema_sources() {
+        let dir = std::env::temp_dir().join(format!("polymenu-schema-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("schema.json");
+        std::fs::write(&path, r#"{ "properties": { "a": { "default": 1 } } }"#).unwrap();
+
+        let source: SchemaSource = serde_json::from_value(json!(path)).unwrap();
+        assert_eq!(source, SchemaSource::Path(path.clone()));
+        let mut data = json!({});
+        assert!(source.load().unwrap().apply(&mut data).is_empty());
+        assert_eq!(data, json!({ "a": 1 }));
+
+        let source: SchemaSource = serde_json::from_value(json!({ "type": "object" })).unwrap();
+        assert_eq!(source, SchemaSource::Inline(json!({ "type": "object" })));
+        assert!(source.load().is_ok());
+
+        assert!(SchemaSource::Path(dir.join("missing.json")).load().is_err());
+        std::fs::write(&path, "not json").unwrap();
+        assert!(SchemaSource::Path(path).load().is_err());
+        assert!(
+            SchemaSource::Inline(json!({ "type": "not-a-type" }))
+                .load()
+                .is_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
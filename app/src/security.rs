@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow, bail};
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::Config;
+
+/// Which hosts the webview can reach (besides the app itself)
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(from = "NetworkRepr", into = "NetworkRepr")]
+pub enum Network {
+    #[default]
+    Deny,
+    Allow,
+    /// Only these domains (over HTTPS), where `*.example.com` matches any subdomain of
+    /// `example.com`
+    Domains(Vec<String>),
+}
+
+/// How `network` is written in `config.toml`: `"deny"`, `"allow"`, or a list of domains
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum NetworkRepr {
+    Access(Access),
+    Domains(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Access {
+    Deny,
+    Allow,
+}
+
+impl From<NetworkRepr> for Network {
+    fn from(value: NetworkRepr) -> Self {
+        match value {
+            NetworkRepr::Access(Access::Deny) => Network::Deny,
+            NetworkRepr::Access(Access::Allow) => Network::Allow,
+            NetworkRepr::Domains(domains) => Network::Domains(domains),
+        }
+    }
+}
+
+impl From<Network> for NetworkRepr {
+    fn from(value: Network) -> Self {
+        match value {
+            Network::Deny => NetworkRepr::Access(Access::Deny),
+            Network::Allow => NetworkRepr::Access(Access::Allow),
+            Network::Domains(domains) => NetworkRepr::Domains(domains),
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    /// Parse `deny`, `allow`, or a comma separated list of domains
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "deny" => Ok(Network::Deny),
+            "allow" => Ok(Network::Allow),
+            _ => {
+                let domains: Vec<String> = s
+                    .split(',')
+                    .map(|d| d.trim().to_string())
+                    .filter(|d| !d.is_empty())
+                    .collect();
+                if domains.is_empty() {
+                    return Err(anyhow!("expected `deny`, `allow`, or a list of domains"));
+                }
+                Ok(Network::Domains(domains))
+            }
+        }
+    }
+}
+
+impl Network {
+    /// Make sure that the domains are valid host names (so they can't inject anything into the
+    /// CSP)
+    fn validate(&self) -> Result<()> {
+        if let Network::Domains(domains) = self {
+            for domain in domains {
+                let host = domain.strip_prefix("*.").unwrap_or(domain);
+                if host.is_empty()
+                    || !host
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                {
+                    bail!("invalid domain in `network`: {domain}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the webview may navigate to (or open a window for) `url`, which isn't part of the
+    /// app itself
+    pub fn allows(&self, url: &Url) -> bool {
+        match self {
+            Network::Deny => false,
+            Network::Allow => true,
+            Network::Domains(domains) => {
+                matches!(url.scheme(), "https" | "wss")
+                    && url
+                        .host_str()
+                        .is_some_and(|host| domains.iter().any(|d| domain_matches(d, host)))
+            }
+        }
+    }
+
+    /// The CSP sources that allow the webview to load resources from the allowed hosts
+    fn sources(&self) -> Vec<String> {
+        match self {
+            Network::Deny => Vec::new(),
+            Network::Allow => ["https:", "http:", "wss:", "ws:"]
+                .map(String::from)
+                .to_vec(),
+            Network::Domains(domains) => domains
+                .iter()
+                .flat_map(|d| [format!("https://{d}"), format!("wss://{d}")])
+                .collect(),
+        }
+    }
+}
+
+/// Whether `host` matches `domain` (following the CSP host source syntax)
+fn domain_matches(domain: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let domain = domain.to_ascii_lowercase();
+    match domain.strip_prefix("*.") {
+        Some(parent) => host
+            .strip_suffix(parent)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == domain,
+    }
+}
+
+/// Whether the webview may navigate to `url` (or open a window for it): either it is part of the
+/// app (or `about:blank`), or the network settings allow it. `data:` and `blob:` documents don't
+/// get the app's CSP (but do get its auth token), so they are only allowed with `network = "allow"`.
+pub fn allows_navigation(app_url: &str, network: &Network, url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    match url.scheme() {
+        "about" => return url.path() == "blank",
+        "data" | "blob" => return *network == Network::Allow,
+        _ => {}
+    }
+    let same_origin = Url::parse(app_url).is_ok_and(|app_url| {
+        app_url.scheme() == url.scheme()
+            && app_url.host_str() == url.host_str()
+            && app_url.port_or_known_default() == url.port_or_known_default()
+    });
+    same_origin || network.allows(&url)
+}
+
+/// The directives of the default CSP, and whether they load resources (so the hosts allowed by
+/// `network` should be added to them). Only the server itself (which includes the mounts) is
+/// allowed by default, so that untrusted input rendered by the app can't load remote scripts or
+/// send data elsewhere. Inline styles are allowed, since many front end frameworks rely on them.
+const DEFAULT_CONTENT_SECURITY_POLICY: &[(&str, &str, bool)] = &[
+    ("default-src", "'self'", true),
+    ("script-src", "'self'", true),
+    ("style-src", "'self' 'unsafe-inline'", true),
+    ("img-src", "'self' data: blob:", true),
+    ("font-src", "'self' data:", true),
+    ("media-src", "'self' blob:", true),
+    ("object-src", "'none'", false),
+    ("base-uri", "'none'", false),
+    ("form-action", "'self'", false),
+    ("frame-ancestors", "'none'", false),
+];
+
+const DEFAULT_HEADERS: &[(&str, &str)] = &[
+    ("x-content-type-options", "nosniff"),
+    ("x-frame-options", "DENY"),
+    ("referrer-policy", "no-referrer"),
+    ("cross-origin-opener-policy", "same-origin"),
+    ("cross-origin-resource-policy", "same-origin"),
+];
+
+fn default_content_security_policy(network: &Network) -> String {
+    let sources = network.sources().join(" ");
+    DEFAULT_CONTENT_SECURITY_POLICY
+        .iter()
+        .map(|(directive, value, fetch)| {
+            if *fetch && !sources.is_empty() {
+                format!("{directive} {value} {sources}")
+            } else {
+                format!("{directive} {value}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The security headers added to every response: the defaults, overridden by the configured
+/// headers (where an empty value removes a default header)
+pub fn security_headers(
+    overrides: &HashMap<String, String>,
+    network: &Network,
+) -> Result<HeaderMap> {
+    network.validate()?;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::try_from(default_content_security_policy(network))?,
+    );
+    for (name, value) in DEFAULT_HEADERS {
+        headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_static(value),
+        );
+    }
+    for (name, value) in overrides {
+        let name = HeaderName::try_from(name)
+            .with_context(|| format!("invalid security header name: {name}"))?;
+        if value.is_empty() {
+            headers.remove(&name);
+            continue;
+        }
+        let value = HeaderValue::try_from(value)
+            .with_context(|| format!("invalid value for security header `{name}`: {value}"))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Add the security headers to a response (unless the handler already set them)
+pub async fn add_security_headers(
+    State(headers): State<HeaderMap>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    for (name, value) in headers.iter() {
+        response
+            .headers_mut()
+            .entry(name)
+            .or_insert_with(|| value.clone());
+    }
+    response
+}
+
+/// The `/api/{endpoint}` endpoints that can be allowed by name in `[permissions]` (commands and
+/// mounts are allowed individually instead)
+const ENDPOINTS: &[&str] = &[
+    "options", "input", "schema", "watch", "events", "launch", "icon", "print", "close",
+];
+
+/// Which parts of the API the app can use. Without a `[permissions]` table the app can use
+/// everything, but once it is set, only what it lists is allowed.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Permissions {
+    /// The `/api/{endpoint}` endpoints that the app can use (e.g. `input`, `print`, `close`)
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+
+    /// The commands that the app can run through `/api/command/{name}`
+    #[serde(default)]
+    pub commands: Vec<String>,
+
+    /// The mounts that the app can access through `/files/{key}` and `/api/mounts/{key}/list`
+    #[serde(default)]
+    pub mounts: Vec<String>,
+}
+
+impl Permissions {
+    /// Make sure that everything the permissions allow exists, so that typos don't silently
+    /// disable parts of the app
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        if let Some(endpoint) = self
+            .endpoints
+            .iter()
+            .find(|e| !ENDPOINTS.contains(&e.as_str()))
+        {
+            bail!(
+                "unknown endpoint in `permissions`: {endpoint} (expected one of: {})",
+                ENDPOINTS.join(", ")
+            );
+        }
+        if let Some(command) = self
+            .commands
+            .iter()
+            .find(|c| !config.commands.contains_key(*c))
+        {
+            bail!("unknown command in `permissions`: {command}");
+        }
+        if let Some(mount) = self.mounts.iter().find(|m| !config.mounts.contains_key(*m)) {
+            bail!("unknown mount in `permissions`: {mount}");
+        }
+        Ok(())
+    }
+
+    /// Whether the app can make a request to `path` (anything outside of `/api` and `/files`, like
+    /// the app itself, is always allowed)
+    fn allows(&self, path: &str) -> bool {
+        let segments: Vec<_> = path
+            .trim_start_matches('/')
+            .split('/')
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy())
+            .collect();
+        let allowed = |list: &[String], name: &str| list.iter().any(|item| item == name);
+        match segments.as_slice() {
+            [api, endpoint, rest @ ..] if api == "api" => match (endpoint.as_ref(), rest) {
+                ("command", [name, ..]) => allowed(&self.commands, name),
+                ("mounts", [name, ..]) => allowed(&self.mounts, name),
+                (endpoint, _) => allowed(&self.endpoints, endpoint),
+            },
+            [files, mount, ..] if files == "files" => allowed(&self.mounts, mount),
+            _ => true,
+        }
+    }
+}
+
+/// Reject requests for the parts of the API that the app's permissions don't allow
+pub async fn enforce_permissions(
+    State(permissions): State<Arc<Permissions>>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !permissions.allows(uri.path()) {
+        return (StatusCode::FORBIDDEN, "forbidden").into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_URL: &str = "polymenu://localhost/";
+
+    #[test]
+    fn navigation_within_the_app_is_allowed() {
+        assert!(allows_navigation(
+            APP_URL,
+            &Network::Deny,
+            "polymenu://localhost/page"
+        ));
+        assert!(allows_navigation(
+            "http://localhost:4444/",
+            &Network::Deny,
+            "http://localhost:4444/page"
+        ));
+        assert!(!allows_navigation(
+            "http://localhost:4444/",
+            &Network::Deny,
+            "http://localhost:4445/page"
+        ));
+        assert!(allows_navigation(APP_URL, &Network::Deny, "about:blank"));
+    }
+
+    #[test]
+    fn documents_without_the_csp_need_network_access() {
+        for url in [
+            "data:text/html,<script>fetch('https://example.com')</script>",
+            "blob:polymenu://localhost/1234",
+            "about:srcdoc",
+            "not a url",
+        ] {
+            assert!(!allows_navigation(APP_URL, &Network::Deny, url), "{url}");
+            assert!(
+                !allows_navigation(APP_URL, &Network::Domains(vec!["example.com".into()]), url),
+                "{url}"
+            );
+        }
+        assert!(allows_navigation(
+            APP_URL,
+            &Network::Allow,
+            "data:text/html,hi"
+        ));
+    }
+
+    #[test]
+    fn navigation_follows_the_network_settings() {
+        let network = Network::Domains(vec!["example.com".into()]);
+        assert!(!allows_navigation(
+            APP_URL,
+            &Network::Deny,
+            "https://example.com/"
+        ));
+        assert!(allows_navigation(
+            APP_URL,
+            &Network::Allow,
+            "http://example.com/"
+        ));
+        assert!(allows_navigation(APP_URL, &network, "https://example.com/"));
+        assert!(!allows_navigation(APP_URL, &network, "http://example.com/"));
+        assert!(!allows_navigation(
+            APP_URL,
+            &network,
+            "https://example.org/"
+        ));
+    }
+
+    #[test]
+    fn wildcard_domains_only_match_subdomains() {
+        assert!(domain_matches("*.example.com", "a.example.com"));
+        assert!(domain_matches("*.example.com", "a.b.EXAMPLE.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "badexample.com"));
+        assert!(domain_matches("example.com", "Example.com"));
+        assert!(!domain_matches("example.com", "a.example.com"));
+        assert!(!domain_matches("example.com", "badexample.com"));
+    }
+
+    #[test]
+    fn network_from_str() {
+        assert_eq!("deny".parse::<Network>().unwrap(), Network::Deny);
+        assert_eq!("allow".parse::<Network>().unwrap(), Network::Allow);
+        assert_eq!(
+            " example.com, *.cdn.org ,".parse::<Network>().unwrap(),
+            Network::Domains(vec!["example.com".into(), "*.cdn.org".into()])
+        );
+        assert!(" , ".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn only_host_names_are_valid_domains() {
+        let valid = |domain: &str| Network::Domains(vec![domain.into()]).validate().is_ok();
+        assert!(valid("example.com"));
+        assert!(valid("*.my-cdn.example.com"));
+        assert!(!valid("*."));
+        assert!(!valid("example.com; script-src *"));
+        assert!(!valid("https://example.com"));
+        assert!(!valid("a.*.example.com"));
+        assert!(Network::Deny.validate().is_ok());
+    }
+
+    fn permissions() -> Permissions {
+        Permissions {
+            endpoints: vec!["input".into(), "print".into()],
+            commands: vec!["open file".into()],
+            mounts: vec!["docs".into()],
+        }
+    }
+
+    #[test]
+    fn only_listed_endpoints_are_allowed() {
+        let permissions = permissions();
+        assert!(permissions.allows("/api/input"));
+        assert!(permissions.allows("/api/input/named"));
+        assert!(permissions.allows("/api/print"));
+        assert!(!permissions.allows("/api/close"));
+        assert!(!permissions.allows("/api/"));
+        assert!(!permissions.allows("//api/close"));
+        assert!(!permissions.allows("/api/%63lose"));
+    }
+
+    #[test]
+    fn commands_are_checked_by_name() {
+        let permissions = permissions();
+        assert!(permissions.allows("/api/command/open%20file"));
+        assert!(!permissions.allows("/api/command/open"));
+        assert!(!permissions.allows("/api/command"));
+        assert!(!permissions.allows("/api/command/"));
+        assert!(!permissions.allows("/api/command/open%20file%2F..%2Frm"));
+    }
+
+    #[test]
+    fn mounts_are_checked_by_name() {
+        let permissions = permissions();
+        assert!(permissions.allows("/api/mounts/docs/list"));
+        assert!(permissions.allows("/files/docs/a/b.txt"));
+        assert!(permissions.allows("/files/%64ocs/a.txt"));
+        assert!(!permissions.allows("/api/mounts/secrets/list"));
+        assert!(!permissions.allows("/files/secrets/a.txt"));
+        assert!(!permissions.allows("/api/mounts"));
+        // The mount's name is the first segment, whatever follows it
+        assert!(!permissions.allows("/files/secrets/docs/a.txt"));
+    }
+
+    #[test]
+    fn the_app_itself_is_always_allowed() {
+        let permissions = Permissions::default();
+        assert!(permissions.allows("/"));
+        assert!(permissions.allows("/index.html"));
+        assert!(permissions.allows("/assets/api/input"));
+        assert!(permissions.allows("/session"));
+        assert!(!permissions.allows("/api/input"));
+        assert!(!permissions.allows("/files/docs/a.txt"));
+    }
+}
@@ -1,12 +1,17 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, convert::Infallible, path::PathBuf, sync::Arc};
 
 use anyhow::{Context, anyhow};
 use axum::{
     Json, Router,
-    extract::{Path, Request, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, Request, State, WebSocketUpgrade},
+    handler::Handler,
+    http::{HeaderMap, StatusCode, header},
     middleware::Next,
-    response::{IntoResponse, Result},
+    response::{
+        IntoResponse, Response, Result,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, get_service, post, put},
 };
 use axum_extra::{
@@ -14,50 +19,167 @@ use axum_extra::{
     extract::cookie::{Cookie, CookieJar, SameSite},
     headers::{Authorization, authorization::Bearer},
 };
-use once_cell::sync::Lazy;
-use rand::{Rng, distr::Alphanumeric};
+use futures_util::{Stream, StreamExt, stream};
+use notify::RecommendedWatcher;
+use percent_encoding::percent_decode_str;
 use serde::Deserialize;
 use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, watch,
+};
 use tokio_util::sync::CancellationToken;
 use tower_http::{compression::CompressionLayer, services::ServeDir, trace::TraceLayer};
 use tracing_subscriber::EnvFilter;
 
 use crate::{
-    config::Config,
+    auth::Auth,
+    config::{Config, InputConfig, MountConfig},
+    desktop::desktop_entries,
+    events::{self, EventBus},
     expansion::expand_path,
-    io::{DataParser, DataSourceKind},
+    files::{self, Mount},
+    icons::{IconLookup, content_type},
+    io::DataParser,
+    listing::{Entry, list},
+    schema::Schemas,
+    security::{add_security_headers, enforce_permissions, security_headers},
+    thumbnail::thumbnail,
+    transform::{Transform, Transforms},
+    watch::{WatchEvent, spawn_watcher},
 };
 
-pub static AUTH_TOKEN: Lazy<String> = Lazy::new(|| {
-    rand::rng()
-        .sample_iter(&Alphanumeric)
-        .take(48)
-        .map(char::from)
-        .collect()
-});
-
 const SESSION_COOKIE_NAME: &str = "session_id";
 
 #[derive(Clone)]
 struct AppState {
     pub config: Config,
     shutdown_token: CancellationToken,
+    watch_events: broadcast::Sender<WatchEvent>,
+    icons: Arc<IconLookup>,
+    mounts: Arc<HashMap<String, Arc<Mount>>>,
+    schemas: Arc<Schemas>,
+    transforms: Arc<Transforms>,
+    /// The compiled transforms of the commands that have one, keyed by command name
+    command_transforms: Arc<HashMap<String, Arc<Transform>>>,
+    events: EventBus,
+    io: ClientIo,
+    on_close: OnClose,
 }
 
-impl AppState {
-    pub fn new(config: Config, shutdown_token: CancellationToken) -> Self {
-        AppState {
-            config,
-            shutdown_token,
-        }
-    }
+/// Bind the server's TCP listener (before the server is started, so that the port it is bound to
+/// is known)
+pub async fn bind(config: &Config) -> anyhow::Result<TcpListener> {
+    let url = config.server_url();
+    TcpListener::bind(&url)
+        .await
+        .with_context(|| format!("could not bind server to {url}"))
+}
+
+/// Bind the server's private Unix socket (before the GUI is started, so that its first requests
+/// don't fail)
+#[cfg(unix)]
+pub async fn bind_private_socket(config: &Config) -> anyhow::Result<Listener> {
+    Ok(Listener::Socket(bind_socket(&config.socket_path())?))
+}
+
+#[cfg(not(unix))]
+pub async fn bind_private_socket(_config: &Config) -> anyhow::Result<Listener> {
+    Err(anyhow!("serving over a socket is only supported on Unix"))
+}
+
+/// Where the server receives requests from
+pub enum Listener {
+    Tcp(TcpListener),
+    /// A private Unix socket (at [`Config::socket_path`])
+    #[cfg(unix)]
+    Socket(tokio::net::UnixListener),
+    /// The webview, through the `polymenu://` protocol. The app's router is sent to the GUI
+    /// instead of being served.
+    InProcess(watch::Sender<Option<Router>>),
+}
+
+/// The STDIN and output of the app, when they aren't this process's own (i.e. when the daemon shows
+/// the app on behalf of a client)
+#[derive(Clone, Default)]
+pub struct ClientIo {
+    pub stdin: Option<Arc<[u8]>>,
+    /// Receives the values printed by the webview
+    pub output: Option<mpsc::UnboundedSender<String>>,
+}
+
+/// What happens when the webview asks to close the app
+#[derive(Clone)]
+pub enum OnClose {
+    Shutdown,
+    /// Ask the GUI to hide the window (the app keeps running in the background)
+    Hide(mpsc::UnboundedSender<()>),
+}
+
+/// The app's router, and everything that needs to stay alive while it is in use
+pub struct App {
+    pub router: Router,
+    _watcher: Option<RecommendedWatcher>,
 }
 
-pub async fn run(config: Config, shutdown_token: CancellationToken) -> anyhow::Result<()> {
+pub fn init_tracing() {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
+}
+
+/// Run the server until the app shuts down
+pub async fn run(
+    config: Config,
+    listener: Listener,
+    auth: Auth,
+    on_close: OnClose,
+    shutdown_token: CancellationToken,
+) -> anyhow::Result<()> {
+    init_tracing();
+    #[cfg(unix)]
+    let socket_path = config.socket_path();
+    // Only the webview can make requests to an in-process server, so they don't need to be
+    // authenticated
+    let auth = (!matches!(listener, Listener::InProcess(_))).then_some(auth);
+    let app = build(
+        config,
+        ClientIo::default(),
+        auth,
+        on_close,
+        shutdown_token.clone(),
+    )
+    .await?;
+    match listener {
+        Listener::Tcp(listener) => axum::serve(listener, app.router)
+            .with_graceful_shutdown(shutdown_signal(shutdown_token))
+            .await
+            .context("Problem starting server"),
+        #[cfg(unix)]
+        Listener::Socket(listener) => {
+            serve_socket(&socket_path, listener, app.router, shutdown_token).await
+        }
+        Listener::InProcess(sender) => {
+            sender
+                .send(Some(app.router.clone()))
+                .map_err(|_| anyhow!("the GUI stopped before the server started"))?;
+            // Keep the watcher alive until the app shuts down
+            shutdown_signal(shutdown_token).await;
+            Ok(())
+        }
+    }
+}
 
+/// Build the router that serves the app (the app shuts down when `shutdown_token` is cancelled).
+/// Requests are only authenticated if `auth` is given.
+pub async fn build(
+    config: Config,
+    io: ClientIo,
+    auth: Option<Auth>,
+    on_close: OnClose,
+    shutdown_token: CancellationToken,
+) -> anyhow::Result<App> {
     let ui_src = {
         let mut path = PathBuf::from(expand_path(config.src.as_ref().expect(
             "`app_src` must be provided either in your config file or as a CLI argument (neither was provided)"
@@ -74,99 +196,462 @@ pub async fn run(config: Config, shutdown_token: CancellationToken) -> anyhow::R
                 Hint: you can build it by running:
                 polymenu {} --compile`",
                 &path,
-                &config
+                config
                     .config
-                    .unwrap()
-                    .into_os_string()
-                    .into_string()
-                    .unwrap(),
+                    .clone()
+                    .unwrap_or_else(Config::default_path)
+                    .display(),
             ));
         }
         path
     };
-    let url = config.server_url();
+    let security_headers = security_headers(
+        &config.security_headers,
+        &config.network.clone().unwrap_or_default(),
+    )?;
+    if let Some(permissions) = &config.permissions {
+        permissions.validate(&config)?;
+    }
     let ui_service = get_service(ServeDir::new(&ui_src));
     let mut mounted = Router::new();
-    for (key, path) in config.mounts.iter() {
-        let expanded_path = expand_path(path).context("failed to expand mount path")?;
+    let mut mounts = HashMap::new();
+    for (key, mount_config) in config.mounts.iter() {
+        let expanded_path =
+            expand_path(&mount_config.path).context("failed to expand mount path")?;
         if !std::fs::exists(&expanded_path)? {
             return Err(anyhow!(
                 "could not mount `{key}`: path `{}` does not exist",
                 &expanded_path
             ));
         }
+        let mount = Mount::new(expanded_path.as_ref(), mount_config)
+            .with_context(|| format!("could not mount `{key}`"))?;
+        let mount = Arc::new(mount);
         mounted = mounted.nest_service(
             &format!("/{key}"),
-            get_service(ServeDir::new(expanded_path)),
+            mount_router(mount.clone(), mount_config),
         );
+        mounts.insert(key.clone(), mount);
+    }
+    // Compile the schemas and transforms once, rather than every time an input is parsed (which
+    // also makes sure that they are valid before the app starts)
+    let mut schemas = Schemas::new();
+    let mut transforms = Transforms::new();
+    for (name, input) in config.all_inputs() {
+        let describe = |what: &str| match name {
+            Some(name) => format!("failed to load {what} for input `{name}`"),
+            None => format!("failed to load input {what}"),
+        };
+        if let Some(schema) = &input.schema {
+            let schema = schema.load().with_context(|| describe("schema"))?;
+            schemas.insert(name.cloned(), Arc::new(schema));
+        }
+        if let Some(filter) = &input.transform {
+            let transform = Transform::new(filter, input.unwrap_transform)
+                .with_context(|| describe("transform"))?;
+            transforms.insert(name.cloned(), Arc::new(transform));
+        }
+    }
+    let mut command_transforms = HashMap::new();
+    for (name, command) in config.commands.iter() {
+        if let Some(filter) = &command.transform {
+            let transform = Transform::new(filter, command.unwrap_transform)
+                .with_context(|| format!("failed to load transform for command `{name}`"))?;
+            command_transforms.insert(name.clone(), Arc::new(transform));
+        }
     }
+
+    let (watch_events, _) = broadcast::channel(16);
+    // The watcher stops watching when dropped, so it needs to live as long as the server
+    let watcher = config
+        .watch
+        .then(|| spawn_watcher(&config, &schemas, &transforms, watch_events.clone()))
+        .transpose()
+        .context("failed to watch inputs")?;
+    let events = EventBus::new();
+    events.forward(watch_events.subscribe(), events::Event::Watch);
+
     let api_routes = Router::new()
         .route("/options", get(options))
         .route("/input", get(read_input))
+        .route("/input/{name}", get(read_named_input))
+        .route("/schema", get(read_schema))
+        .route("/schema/{name}", get(read_named_schema))
+        .route("/watch", get(watch))
+        .route("/events", get(event_socket))
         .route("/command/{name}", post(command))
+        .route("/launch/{id}", post(launch))
+        .route("/icon/{*name}", get(icon))
+        .route("/mounts/{name}/list", get(list_mount))
         .route("/print", put(print_value))
         .route("/close", put(close));
 
-    let private_routes = Router::new()
+    let permissions = config.permissions.clone().map(Arc::new);
+    let mut private_routes = Router::new()
         .nest("/api", api_routes)
         .nest("/files", mounted)
         .fallback_service(ui_service)
-        .with_state(AppState::new(config, shutdown_token.clone()))
-        .route_layer(axum::middleware::from_fn(require_auth));
+        .with_state(AppState {
+            icons: Arc::new(IconLookup::new(config.icon_theme.clone())),
+            config,
+            shutdown_token,
+            watch_events,
+            mounts: Arc::new(mounts),
+            schemas: Arc::new(schemas),
+            transforms: Arc::new(transforms),
+            command_transforms: Arc::new(command_transforms),
+            events,
+            io,
+            on_close,
+        });
+    if let Some(permissions) = permissions {
+        private_routes = private_routes.route_layer(axum::middleware::from_fn_with_state(
+            permissions,
+            enforce_permissions,
+        ));
+    }
+    if let Some(auth) = &auth {
+        private_routes = private_routes.route_layer(axum::middleware::from_fn_with_state(
+            auth.clone(),
+            require_auth,
+        ));
+    }
 
     let app = Router::new()
         .route("/session", post(establish_session))
+        .with_state(auth)
         .merge(private_routes)
+        .layer(axum::middleware::from_fn_with_state(
+            security_headers,
+            add_security_headers,
+        ))
         .layer(CompressionLayer::new())
         .layer(TraceLayer::new_for_http());
 
-    let listener = tokio::net::TcpListener::bind(url).await.unwrap();
-    axum::serve(listener, app)
+    Ok(App {
+        router: app,
+        _watcher: watcher,
+    })
+}
+
+/// Serve the files in a mount (with thumbnails), and handle writes if the mount is writable
+fn mount_router(mount: Arc<Mount>, config: &MountConfig) -> Router {
+    // Directories would be served as their `index.html`, which the access rules weren't checked
+    // against
+    let serve_dir = get_service(ServeDir::new(&mount.root).append_index_html_on_directories(false));
+    let mut router = Router::new().fallback_service(serve_dir.clone());
+    if config.writable {
+        router = router.route(
+            "/{*path}",
+            put(write_file.layer(DefaultBodyLimit::max(config.max_file_size())))
+                .delete(delete_file)
+                .post(rename_file)
+                .fallback_service(serve_dir),
+        );
+    }
+    router
+        .layer(axum::middleware::from_fn_with_state(
+            mount.clone(),
+            serve_thumbnail,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            mount.clone(),
+            restrict_mount_access,
+        ))
+        .with_state(mount)
+}
+
+/// Hide files that the mount's access rules don't allow (as if they don't exist)
+async fn restrict_mount_access(
+    State(mount): State<Arc<Mount>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = percent_decode_str(request.uri().path())
+        .decode_utf8_lossy()
+        .trim_start_matches('/')
+        .to_string();
+    // Checking the rules reads the file system
+    let allowed = {
+        let mount = mount.clone();
+        tokio::task::spawn_blocking(move || mount.allows(path.as_ref()))
+            .await
+            .unwrap_or(false)
+    };
+    if !allowed {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+struct ThumbnailQuery {
+    thumb: Option<u32>,
+}
+
+/// Serve a cached thumbnail instead of the file itself when a mounted image is requested with
+/// `?thumb={size}`
+async fn serve_thumbnail(
+    State(mount): State<Arc<Mount>>,
+    Query(query): Query<ThumbnailQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(size) = query.thumb else {
+        return next.run(request).await;
+    };
+    let path = percent_decode_str(request.uri().path()).decode_utf8_lossy();
+    let path = match mount.resolve(path.trim_start_matches('/')).await {
+        Ok(path) => path,
+        Err(e) => return e.into_response(),
+    };
+    if !path.is_file() {
+        return next.run(request).await;
+    }
+    let result = tokio::task::spawn_blocking(move || thumbnail(&path, size.max(1))).await;
+    match result {
+        Ok(Ok(Some(thumbnail_path))) => match tokio::fs::read(&thumbnail_path).await {
+            Ok(body) => ([(header::CONTENT_TYPE, "image/png")], body).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")).into_response(),
+        },
+        // The image is already smaller than the requested thumbnail, or the file can't be
+        // thumbnailed (e.g. it isn't an image), so serve the file itself
+        Ok(Ok(None) | Err(_)) => next.run(request).await,
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")).into_response(),
+    }
+}
+
+/// Create or replace a file in a writable mount
+async fn write_file(
+    State(mount): State<Arc<Mount>>,
+    Path(path): Path<String>,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let path = mount.resolve(&path).await?;
+    files::write(&path, &body).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a file (or empty directory) in a writable mount
+async fn delete_file(
+    State(mount): State<Arc<Mount>>,
+    Path(path): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let path = mount.resolve(&path).await?;
+    files::delete(&path).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct RenameRequest {
+    rename_to: String,
+}
+
+/// Move a file (or directory) within a writable mount
+async fn rename_file(
+    State(mount): State<Arc<Mount>>,
+    Path(path): Path<String>,
+    Json(req): Json<RenameRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let from = mount.resolve(&path).await?;
+    let to = mount.resolve(&req.rename_to).await?;
+    files::rename(&from, &to).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serve the app on a Unix socket that only the current user can connect to
+#[cfg(unix)]
+async fn serve_socket(
+    path: &std::path::Path,
+    listener: tokio::net::UnixListener,
+    app: Router,
+    shutdown_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let result = axum::serve(listener, app)
         .with_graceful_shutdown(shutdown_signal(shutdown_token))
         .await
-        .context("Problem starting server")
+        .context("Problem starting server");
+    let _ = std::fs::remove_file(path);
+    result
+}
+
+/// Bind a Unix socket that only the current user can connect to
+#[cfg(unix)]
+pub fn bind_socket(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = path
+        .parent()
+        .context("socket path has no parent directory")?;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("could not create socket directory: {dir:?}"))?;
+    // A socket left behind by a process that has exited would prevent binding
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("could not bind to socket: {path:?}"))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("could not set permissions of socket: {path:?}"))?;
+    Ok(listener)
 }
 
 async fn shutdown_signal(token: CancellationToken) {
     token.cancelled().await;
 }
 
+/// Exchange the window's token for a session cookie (which expires on its own, unlike the token)
 async fn establish_session(
-    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(auth): State<Option<Auth>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
     jar: CookieJar,
 ) -> Result<(CookieJar, StatusCode), StatusCode> {
-    if bearer.token() != *AUTH_TOKEN {
-        return Err(axum::http::StatusCode::UNAUTHORIZED);
-    }
-    let mut cookie = Cookie::new(SESSION_COOKIE_NAME, AUTH_TOKEN.clone());
+    // Requests to an in-process server aren't authenticated, so there is nothing to establish
+    let Some(auth) = auth else {
+        return Ok((jar, StatusCode::NO_CONTENT));
+    };
+    let session_id = bearer
+        .and_then(|TypedHeader(Authorization(bearer))| auth.establish_session(bearer.token()))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let mut cookie = Cookie::new(SESSION_COOKIE_NAME, session_id);
     cookie.set_same_site(SameSite::Strict);
     cookie.set_http_only(true);
     Ok((jar.add(cookie), StatusCode::NO_CONTENT))
 }
 
 async fn require_auth(
+    State(auth): State<Auth>,
     jar: CookieJar,
     bearer: Option<TypedHeader<Authorization<Bearer>>>,
     req: Request,
     next: Next,
 ) -> impl IntoResponse {
-    let ok_header = bearer.as_ref().is_some_and(|h| h.token() == *AUTH_TOKEN);
+    let ok_header = bearer
+        .as_ref()
+        .is_some_and(|h| auth.is_window_token(h.token()));
     let ok_cookie = jar
         .get(SESSION_COOKIE_NAME)
-        .is_some_and(|c| c.value() == *AUTH_TOKEN);
+        .is_some_and(|c| auth.use_session(c.value()));
     if !(ok_header || ok_cookie) {
         return (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
     }
     next.run(req).await
 }
 
-async fn read_input(State(state): State<AppState>) -> Json<Value> {
-    let parser: DataParser = state.config.into();
-    Json(parser.parse(None, None).await.unwrap())
+async fn read_input(State(state): State<AppState>) -> Result<Json<Value>> {
+    parse_input(input_parser(&state, None, state.config.default_input())).await
+}
+
+async fn read_named_input(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>> {
+    let input = named_input(&state, &name)?;
+    parse_input(input_parser(&state, Some(name), input)).await
+}
+
+async fn read_schema(State(state): State<AppState>) -> Result<Json<Value>, (StatusCode, String)> {
+    input_schema(&state, None)
+}
+
+async fn read_named_schema(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    named_input(&state, &name)?;
+    input_schema(&state, Some(name))
+}
+
+fn named_input(state: &AppState, name: &str) -> Result<InputConfig, (StatusCode, String)> {
+    let input = state
+        .config
+        .inputs
+        .get(name)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Input not found: {name}")))?;
+    Ok(input.clone())
+}
+
+/// Serve an input's schema, so the webview can use it (e.g. to generate types)
+fn input_schema(
+    state: &AppState,
+    name: Option<String>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let schema = state
+        .schemas
+        .get(&name)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Input has no schema".to_string()))?;
+    Ok(Json(schema.schema.clone()))
+}
+
+/// A parser for an input, using its compiled schema and transform
+fn input_parser(state: &AppState, name: Option<String>, input: InputConfig) -> DataParser {
+    DataParser::from(input)
+        .with_schema(state.schemas.get(&name).cloned())
+        .with_transform(state.transforms.get(&name).cloned())
+        .with_stdin(state.io.stdin.clone())
+}
+
+/// Parse an input. Inputs read from files or commands are re-read on every request, so the
+/// webview can re-fetch them at any time.
+async fn parse_input(parser: DataParser) -> Result<Json<Value>> {
+    let data = parser
+        .parse(None, None)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))?;
+    Ok(Json(data))
+}
+
+/// Stream changes to watched inputs and mounts to the webview as server-sent events
+async fn watch(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = stream::unfold(state.watch_events.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let event = Event::default()
+                        .json_data(&event)
+                        .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()));
+                    return Some((Ok(event), rx));
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    // End the stream on shutdown, otherwise the server will wait for it forever
+    .take_until(state.shutdown_token.cancelled_owned());
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Connect the webview to the event bus over a WebSocket
+async fn event_socket(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, (StatusCode, String)> {
+    // Browsers send cookies with WebSocket requests from any site, so make sure the request came
+    // from the app itself
+    let origin = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok());
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+    if let Some(origin) = origin
+        && origin.split_once("://").map(|(_, host)| host) != host
+    {
+        return Err((StatusCode::FORBIDDEN, format!("Invalid origin: {origin}")));
+    }
+    Ok(ws.on_upgrade(move |socket| async move {
+        state.events.serve(socket, state.shutdown_token).await
+    }))
 }
 
 async fn close(State(state): State<AppState>) {
-    state.shutdown_token.cancel();
+    match &state.on_close {
+        OnClose::Shutdown => state.shutdown_token.cancel(),
+        OnClose::Hide(hide) => {
+            let _ = hide.send(());
+        }
+    }
 }
 
 async fn options(State(state): State<AppState>) -> Json<HashMap<String, Value>> {
@@ -178,9 +663,14 @@ struct PrintRequest {
     values: Vec<String>,
 }
 
-async fn print_value(Json(req): Json<PrintRequest>) {
+async fn print_value(State(state): State<AppState>, Json(req): Json<PrintRequest>) {
     for v in req.values {
-        println!("{v}")
+        match &state.io.output {
+            Some(output) => {
+                let _ = output.send(v);
+            }
+            None => println!("{v}"),
+        }
     }
 }
 
@@ -199,16 +689,120 @@ async fn command(
         .config
         .commands
         .get(&name)
-        .with_context(|| format!("Command not found: {name}"))
-        .unwrap();
-    let data = DataParser::new(
-        DataSourceKind::Command(cmd.clone()),
-        cmd.output_format,
-        None,
-    )
-    .parse(req.args.as_ref(), req.stdin)
-    .await
-    .with_context(|| format!("Could not parse output for command: {name}"))
-    .unwrap();
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Command not found: {name}")))?;
+    state
+        .events
+        .publish(events::Event::CommandStarted { name: name.clone() });
+    let result = DataParser::from(cmd.clone())
+        .with_transform(state.command_transforms.get(&name).cloned())
+        .parse(req.args.as_ref(), req.stdin)
+        .await
+        .with_context(|| format!("Could not parse output for command: {name}"));
+    state.events.publish(events::Event::CommandFinished {
+        name: name.clone(),
+        success: result.is_ok(),
+    });
+    let data = result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))?;
     Ok(Json(data))
 }
+
+/// Launch an installed application by its desktop file ID
+async fn launch(Path(id): Path<String>) -> Result<StatusCode, (StatusCode, String)> {
+    // Reading the desktop entries walks the XDG application directories
+    let entries = tokio::task::spawn_blocking(desktop_entries)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")))?;
+    let entry = entries.into_iter().find(|e| e.id == id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Application not found: {id}"),
+        )
+    })?;
+    entry
+        .launch()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct IconQuery {
+    #[serde(default = "default_icon_size")]
+    size: u32,
+    #[serde(default = "default_icon_scale")]
+    scale: u32,
+}
+
+fn default_icon_size() -> u32 {
+    48
+}
+
+fn default_icon_scale() -> u32 {
+    1
+}
+
+/// The largest icon size and scale that can be requested
+const MAX_ICON_SIZE: u32 = 1024;
+const MAX_ICON_SCALE: u32 = 8;
+
+/// Serve the icon file for an icon name from the current icon theme (or for the absolute path of
+/// an installed application's icon)
+async fn icon(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<IconQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("Icon not found: {name}"));
+    let is_absolute = std::path::Path::new(&name).is_absolute();
+    if !is_absolute && (name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.')) {
+        return Err(not_found());
+    }
+    let size = query.size.clamp(1, MAX_ICON_SIZE);
+    let scale = query.scale.clamp(1, MAX_ICON_SCALE);
+    // Looking up icons reads the file system
+    let path = {
+        let (icons, name) = (state.icons.clone(), name.clone());
+        tokio::task::spawn_blocking(move || icons.find(&name, size, scale))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")))?
+            .ok_or_else(not_found)?
+    };
+    let body = tokio::fs::read(&path)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type(&path)),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        body,
+    ))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    path: PathBuf,
+    #[serde(default)]
+    recursive: bool,
+    glob: Option<String>,
+}
+
+/// List the files in a directory of a mount
+async fn list_mount(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<Entry>>, (StatusCode, String)> {
+    let mount = state
+        .mounts
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Mount not found: {name}")))?;
+    let entries = tokio::task::spawn_blocking(move || {
+        list(mount, &query.path, query.recursive, query.glob.as_deref())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")))?
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+    Ok(Json(entries))
+}
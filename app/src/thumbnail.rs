@@ -0,0 +1,220 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result, anyhow};
+use md5::{Digest, Md5};
+use url::Url;
+
+/// Thumbnail sizes from the freedesktop thumbnail spec, and the cache directory for each
+const SIZES: &[(u32, &str)] = &[
+    (128, "normal"),
+    (256, "large"),
+    (512, "x-large"),
+    (1024, "xx-large"),
+];
+
+/// Find (or generate) a thumbnail of the image at `path` that is at least `size` pixels in its
+/// largest dimension, using the shared freedesktop thumbnail cache so that thumbnails can be
+/// reused by (and from) file managers. Returns `None` if the image is already small enough to be
+/// used as its own thumbnail.
+pub fn thumbnail(path: &Path, size: u32) -> Result<Option<PathBuf>> {
+    let cache_dir = dirs::cache_dir()
+        .context("could not find cache directory")?
+        .join("thumbnails");
+    thumbnail_in(&cache_dir, path, size)
+}
+
+/// `thumbnail`, with the thumbnails stored under `cache_dir` (`$XDG_CACHE_HOME/thumbnails`)
+fn thumbnail_in(cache_dir: &Path, path: &Path, size: u32) -> Result<Option<PathBuf>> {
+    let path = fs::canonicalize(path).with_context(|| format!("could not find: {path:?}"))?;
+    let (width, height) = image::image_dimensions(&path)
+        .with_context(|| format!("could not read image dimensions: {path:?}"))?;
+    let &(size, dir) = SIZES
+        .iter()
+        .find(|(s, _)| *s >= size)
+        .unwrap_or(&SIZES[SIZES.len() - 1]);
+    if width <= size && height <= size {
+        return Ok(None);
+    }
+
+    let uri = Url::from_file_path(&path)
+        .map_err(|_| anyhow!("could not create URI for: {path:?}"))?
+        .to_string();
+    let mtime = fs::metadata(&path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .to_string();
+    let cache_dir = cache_dir.join(dir);
+    let thumbnail_path = cache_dir.join(thumbnail_name(&uri));
+    if is_up_to_date(&thumbnail_path, &uri, &mtime) {
+        return Ok(Some(thumbnail_path));
+    }
+
+    let image = image::open(&path)
+        .with_context(|| format!("could not decode image: {path:?}"))?
+        .thumbnail(size, size)
+        .into_rgba8();
+    create_private_dir(&cache_dir)?;
+    // Write to a temporary file first so that other programs never see a partial thumbnail
+    let tmp_path = cache_dir.join(format!(
+        ".{}.{:x}.tmp",
+        thumbnail_name(&uri),
+        rand::random::<u64>()
+    ));
+    {
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("could not create thumbnail: {tmp_path:?}"))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("Thumb::URI".to_string(), uri)?;
+        encoder.add_text_chunk("Thumb::MTime".to_string(), mtime)?;
+        encoder.write_header()?.write_image_data(image.as_raw())?;
+    }
+    set_private_permissions(&tmp_path, 0o600)?;
+    fs::rename(&tmp_path, &thumbnail_path)
+        .with_context(|| format!("could not save thumbnail: {thumbnail_path:?}"))?;
+    Ok(Some(thumbnail_path))
+}
+
+/// The file name of a thumbnail in the cache: the MD5 hash of its image's URI
+fn thumbnail_name(uri: &str) -> String {
+    format!("{:x}.png", Md5::digest(uri.as_bytes()))
+}
+
+/// Whether a cached thumbnail exists and was generated from the current version of its image
+fn is_up_to_date(thumbnail_path: &Path, uri: &str, mtime: &str) -> bool {
+    let Ok(file) = File::open(thumbnail_path) else {
+        return false;
+    };
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_ignore_text_chunk(false);
+    let Ok(reader) = decoder.read_info() else {
+        return false;
+    };
+    let text = &reader.info().uncompressed_latin1_text;
+    let get = |key: &str| {
+        text.iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.as_str())
+    };
+    get("Thumb::URI") == Some(uri) && get("Thumb::MTime") == Some(mtime)
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("could not create directory: {dir:?}"))?;
+    set_private_permissions(dir, 0o700)
+}
+
+#[cfg(unix)]
+fn set_private_permissions(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("could not set permissions of: {path:?}"))
+}
+
+#[cfg(not(unix))]
+fn set_private_permissions(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("polymenu-thumbnail-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn text_chunk(path: &Path, key: &str) -> Option<String> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path).unwrap()));
+        decoder.set_ignore_text_chunk(false);
+        let reader = decoder.read_info().unwrap();
+        reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.clone())
+    }
+
+    #[test]
+    fn thumbnails_are_named_after_the_md5_of_the_uri() {
+        // The example from the thumbnail spec
+        assert_eq!(
+            thumbnail_name("file:///home/jens/photos/me.png"),
+            "c6ee772d9e49320e97ec29a7eb5b1697.png"
+        );
+    }
+
+    #[test]
+    fn thumbnails_are_cached_by_size() {
+        let dir = temp_dir("cache");
+        let cache_dir = dir.join("thumbnails");
+        let image_path = dir.join("image.png");
+        image::RgbaImage::new(600, 300).save(&image_path).unwrap();
+        let uri = Url::from_file_path(&image_path).unwrap().to_string();
+
+        let path = thumbnail_in(&cache_dir, &image_path, 200).unwrap().unwrap();
+        assert_eq!(path, cache_dir.join("large").join(thumbnail_name(&uri)));
+        assert_eq!(image::image_dimensions(&path).unwrap(), (256, 128));
+        assert_eq!(text_chunk(&path, "Thumb::URI"), Some(uri));
+        let path = thumbnail_in(&cache_dir, &image_path, 1).unwrap().unwrap();
+        assert!(path.starts_with(cache_dir.join("normal")));
+        // The image is already smaller than the largest thumbnail size
+        assert!(
+            thumbnail_in(&cache_dir, &image_path, 600)
+                .unwrap()
+                .is_none()
+        );
+        assert!(thumbnail_in(&cache_dir, &dir.join("missing.png"), 128).is_err());
+        fs::write(dir.join("text.png"), "not an image").unwrap();
+        assert!(thumbnail_in(&cache_dir, &dir.join("text.png"), 128).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stale_thumbnails_are_regenerated() {
+        let dir = temp_dir("stale");
+        let cache_dir = dir.join("thumbnails");
+        let image_path = dir.join("image.png");
+        image::RgbaImage::new(300, 300).save(&image_path).unwrap();
+        let uri = Url::from_file_path(&image_path).unwrap().to_string();
+        let mtime = |path: &Path| {
+            let modified = fs::metadata(path).unwrap().modified().unwrap();
+            modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string()
+        };
+
+        let path = thumbnail_in(&cache_dir, &image_path, 128).unwrap().unwrap();
+        let old_mtime = mtime(&image_path);
+        assert_eq!(text_chunk(&path, "Thumb::MTime"), Some(old_mtime.clone()));
+        assert!(is_up_to_date(&path, &uri, &old_mtime));
+        assert!(!is_up_to_date(&path, "file:///other.png", &old_mtime));
+        assert!(!is_up_to_date(&dir.join("missing.png"), &uri, &old_mtime));
+
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(&image_path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let new_mtime = mtime(&image_path);
+        assert!(!is_up_to_date(&path, &uri, &new_mtime));
+        let path = thumbnail_in(&cache_dir, &image_path, 128).unwrap().unwrap();
+        assert!(is_up_to_date(&path, &uri, &new_mtime));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow, bail};
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Filter, Native, RcIter};
+use jaq_json::Val;
+use serde_json::Value;
+
+/// The compiled transforms of an app's inputs, keyed by input name (`None` for the default input)
+pub type Transforms = HashMap<Option<String>, Arc<Transform>>;
+
+/// A compiled jq filter (e.g. `.[] | select(.enabled) | {key: .name}`)
+pub struct Transform {
+    filter: String,
+    compiled: Filter<Native<Val>>,
+    unwrap: bool,
+}
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transform")
+            .field("filter", &self.filter)
+            .field("unwrap", &self.unwrap)
+            .finish()
+    }
+}
+
+impl Transform {
+    /// Parse and compile `filter`. If `unwrap` is set, the filter's only output is used instead
+    /// of an array of its outputs.
+    pub fn new(filter: &str, unwrap: bool) -> Result<Self> {
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+        let program = File {
+            code: filter,
+            path: (),
+        };
+        let modules = loader.load(&arena, program).map_err(|errors| {
+            let errors: Vec<_> = errors.into_iter().map(|(_, e)| e).collect();
+            anyhow!("could not parse transform `{filter}`: {errors:?}")
+        })?;
+        let compiled = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errors| {
+                let errors: Vec<_> = errors.into_iter().map(|(_, e)| e).collect();
+                anyhow!("could not compile transform `{filter}`: {errors:?}")
+            })?;
+        Ok(Self {
+            filter: filter.to_string(),
+            compiled,
+            unwrap,
+        })
+    }
+
+    /// Run the filter on `data`, and collect its outputs into an array. If `unwrap` is set, the
+    /// filter's only output is returned instead (and it is an error if it doesn't produce exactly
+    /// one), so the result's shape never depends on the data.
+    pub fn apply(&self, data: Value) -> Result<Value> {
+        let filter = &self.filter;
+        let inputs = RcIter::new(core::iter::empty());
+        let mut outputs = self
+            .compiled
+            .run((Ctx::new([], &inputs), Val::from(data)))
+            .map(|output| {
+                output
+                    .map(Value::from)
+                    .map_err(|e| anyhow!("transform `{filter}` failed: {e}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !self.unwrap {
+            return Ok(Value::Array(outputs));
+        }
+        if outputs.len() != 1 {
+            bail!(
+                "transform `{filter}` produced {} outputs, but `unwrap_transform` requires exactly one",
+                outputs.len()
+            );
+        }
+        Ok(outputs.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn records() -> Value {
+        json!([
+            { "name": "a", "enabled": true },
+            { "name": "b", "enabled": false },
+            { "name": "c", "enabled": true },
+        ])
+    }
+
+    #[test]
+    fn outputs_are_always_collected() {
+        let transform = Transform::new(".[] | select(.enabled) | .name", false).unwrap();
+        assert_eq!(transform.apply(records()).unwrap(), json!(["a", "c"]));
+        let transform = Transform::new(".[] | select(.name == \"b\")", false).unwrap();
+        assert_eq!(
+            transform.apply(records()).unwrap(),
+            json!([{ "name": "b", "enabled": false }])
+        );
+        let transform = Transform::new(".[] | select(.name == \"d\")", false).unwrap();
+        assert_eq!(transform.apply(records()).unwrap(), json!([]));
+    }
+
+    #[test]
+    fn unwrap_requires_exactly_one_output() {
+        let transform = Transform::new("map(.name)", true).unwrap();
+        assert_eq!(transform.apply(records()).unwrap(), json!(["a", "b", "c"]));
+        let transform = Transform::new(".[] | .name", true).unwrap();
+        assert!(transform.apply(records()).is_err());
+        let transform = Transform::new("empty", true).unwrap();
+        assert!(transform.apply(records()).is_err());
+    }
+
+    #[test]
+    fn invalid_filters_fail_to_compile() {
+        assert!(Transform::new(".[] |", false).is_err());
+        assert!(Transform::new("not_a_function", false).is_err());
+    }
+
+    #[test]
+    fn runtime_errors_are_reported() {
+        let transform = Transform::new(".[] | .name + 1", false).unwrap();
+        assert!(transform.apply(records()).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::sleep;
+
+use crate::config::{Config, InputConfig};
+use crate::expansion::expand_path;
+use crate::files::Mount;
+use crate::io::DataParser;
+use crate::schema::{Schema, Schemas};
+use crate::transform::{Transform, Transforms};
+
+/// How long to wait for related file system events (e.g. from an editor's atomic save) before
+/// reloading
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A change to one of the app's watched inputs or mounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WatchEvent {
+    /// An input file changed (`name` is `null` for the default input)
+    Input {
+        name: Option<String>,
+        #[serde(flatten)]
+        result: ReloadResult,
+    },
+    /// Files in a mounted directory changed (`paths` are relative to the mount)
+    Mount { name: String, paths: Vec<PathBuf> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadResult {
+    Data(Value),
+    Error(String),
+}
+
+struct WatchedInput {
+    name: Option<String>,
+    path: PathBuf,
+    input: InputConfig,
+    schema: Option<Arc<Schema>>,
+    transform: Option<Arc<Transform>>,
+}
+
+/// Watch the app's file inputs and mounted directories, sending a `WatchEvent` with the freshly
+/// parsed data (or changed paths) whenever they change. Watching stops when the returned watcher
+/// is dropped.
+pub fn spawn_watcher(
+    config: &Config,
+    schemas: &Schemas,
+    transforms: &Transforms,
+    events: broadcast::Sender<WatchEvent>,
+) -> Result<RecommendedWatcher> {
+    let inputs = config
+        .all_inputs()
+        .filter_map(|(name, input)| {
+            let path = input.file.as_ref().map(resolve_input_path)?;
+            let name = name.cloned();
+            let schema = schemas.get(&name).cloned();
+            let transform = transforms.get(&name).cloned();
+            Some(path.map(|path| WatchedInput {
+                name,
+                path,
+                input,
+                schema,
+                transform,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mounts = config
+        .mounts
+        .iter()
+        .map(|(name, config)| {
+            let mount = Mount::new(expand_path(&config.path)?.as_ref(), config)
+                .with_context(|| format!("could not watch mount `{name}`"))?;
+            Ok((name.clone(), mount))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && !matches!(event.kind, EventKind::Access(_))
+        {
+            let _ = tx.send(event.paths);
+        }
+    })
+    .context("failed to initialize file watcher")?;
+    for input in inputs.iter() {
+        // Watch the parent directory (rather than the file itself) so that we still receive
+        // events after a file is replaced by an atomic rename
+        let dir = input.path.parent().unwrap_or(Path::new("/"));
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("could not watch input file: {:?}", input.path))?;
+    }
+    for (name, mount) in mounts.iter() {
+        watcher
+            .watch(&mount.root, RecursiveMode::Recursive)
+            .with_context(|| format!("could not watch mount `{name}`"))?;
+    }
+
+    tokio::spawn(async move {
+        while let Some(paths) = rx.recv().await {
+            let mut changed: HashSet<PathBuf> = paths.into_iter().collect();
+            sleep(DEBOUNCE_INTERVAL).await;
+            while let Ok(paths) = rx.try_recv() {
+                changed.extend(paths);
+            }
+
+            for input in inputs.iter().filter(|i| changed.contains(&i.path)) {
+                let parser = DataParser::from(input.input.clone())
+                    .with_schema(input.schema.clone())
+                    .with_transform(input.transform.clone());
+                let result = match parser.parse(None, None).await {
+                    Ok(data) => ReloadResult::Data(data),
+                    Err(e) => ReloadResult::Error(format!("{e:#}")),
+                };
+                let _ = events.send(WatchEvent::Input {
+                    name: input.name.clone(),
+                    result,
+                });
+            }
+            for (name, mount) in mounts.iter() {
+                let paths: Vec<PathBuf> = changed
+                    .iter()
+                    .filter_map(|p| p.strip_prefix(&mount.root).ok())
+                    .filter(|p| mount.allows(p))
+                    .map(Path::to_path_buf)
+                    .collect();
+                if !paths.is_empty() {
+                    let _ = events.send(WatchEvent::Mount {
+                        name: name.clone(),
+                        paths,
+                    });
+                }
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+/// Resolve an input file to an absolute path, so that it can be compared with the paths reported
+/// by the watcher
+fn resolve_input_path(path: &PathBuf) -> Result<PathBuf> {
+    let path = PathBuf::from(expand_path(path)?);
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("input file has no file name: {path:?}"))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = std::fs::canonicalize(dir)
+        .with_context(|| format!("could not resolve directory of input file: {path:?}"))?;
+    Ok(dir.join(file_name))
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+
+/// `$XDG_DATA_HOME` (`~/.local/share` by default), followed by each directory in `$XDG_DATA_DIRS`
+pub fn data_dirs() -> Vec<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local/share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from))
+        .collect()
+}
+
+/// Parse the groups of a key file (the INI-like format of `.desktop` files and `index.theme`).
+/// If a key appears more than once in a group, its first value is used.
+pub fn parse_key_file(src: &str) -> HashMap<&str, HashMap<&str, &str>> {
+    let mut groups: HashMap<&str, HashMap<&str, &str>> = HashMap::new();
+    let mut group = None;
+    for line in src.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            group = Some(name);
+            continue;
+        }
+        if let Some(group) = group
+            && let Some((key, value)) = line.split_once('=')
+        {
+            groups
+                .entry(group)
+                .or_default()
+                .entry(key.trim())
+                .or_insert(value.trim());
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_files_are_split_into_groups() {
+        let src = "\
+# A comment
+ignored=outside of a group
+[Desktop Entry]
+Name = App
+Exec=app --flag=value
+Name=Duplicate
+
+[Desktop Action new]
+; Another comment
+Name=New window
+";
+        let groups = parse_key_file(src);
+        assert_eq!(groups.len(), 2);
+        let entry = &groups["Desktop Entry"];
+        assert_eq!(entry["Name"], "App");
+        assert_eq!(entry["Exec"], "app --flag=value");
+        assert_eq!(groups["Desktop Action new"]["Name"], "New window");
+    }
+}
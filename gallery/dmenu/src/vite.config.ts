@@ -11,7 +11,7 @@ export default defineConfig({
   server: {
     port,
     proxy: {
-      "/api": apiUrl,
+      "/api": { target: apiUrl, ws: true },
       "/files": apiUrl,
       "/session": apiUrl,
     }